@@ -7,7 +7,7 @@ use axum::{
     response::{Html, Json},
     routing::get,
 };
-use real::{IpExtractor, RealIp, RealIpLayer};
+use real::{RealIp, RealIpLayer};
 use serde_json::json;
 use std::net::SocketAddr;
 use tower::ServiceBuilder;
@@ -27,12 +27,16 @@ async fn main() {
     println!("  • GET /              - Hello World with IP info (using default layer)");
     println!("  • GET /ip            - JSON response with IP details (using default layer)");
     println!("  • GET /strict        - JSON response with strict IP validation");
+    println!("  • GET /behind-lb     - JSON response trusting one X-Forwarded-For hop");
     println!("  • GET /debug         - Debug endpoint showing all connection info");
     println!();
     println!("Test with headers:");
     println!("  curl -H 'X-Real-IP: 203.0.113.42' http://localhost:3000/ip");
     println!("  curl -H 'X-Forwarded-For: 198.51.100.1, 192.168.1.1' http://localhost:3000/strict");
     println!("  curl -H 'CF-Connecting-IP: 192.0.2.100' http://localhost:3000/ip");
+    println!(
+        "  curl -H 'X-Forwarded-For: 198.51.100.1, 203.0.113.7' http://localhost:3000/behind-lb"
+    );
     println!();
 
     axum::serve(
@@ -58,9 +62,14 @@ fn create_app() -> Router {
         .route("/", get(ip_strict_handler))
         .layer(strict_layer);
 
+    // one load balancer in front of the service appends to X-Forwarded-For
+    let hops_layer = RealIpLayer::trusted_hops(1);
+    let hops_router = Router::new().route("/", get(ip_handler)).layer(hops_layer);
+
     // merge the default (root) router instead of nesting it at "/"
     app.merge(default_router)
         .nest("/strict", strict_router)
+        .nest("/behind-lb", hops_router)
         .route("/debug", get(debug_handler))
 }
 
@@ -178,10 +187,10 @@ async fn debug_handler(
     ];
 
     for header_name in &ip_header_names {
-        if let Some(header_value) = headers.get(*header_name) {
-            if let Ok(value_str) = header_value.to_str() {
-                ip_headers.insert(*header_name, value_str.to_string());
-            }
+        if let Some(header_value) = headers.get(*header_name)
+            && let Ok(value_str) = header_value.to_str()
+        {
+            ip_headers.insert(*header_name, value_str.to_string());
         }
     }
 
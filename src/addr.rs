@@ -0,0 +1,142 @@
+/* src/addr.rs */
+
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use crate::error::{RealIpError, Result};
+
+/// A client address with an optional port, as found in forwarding headers.
+///
+/// Parses bare addresses as well as the `ip:port` and `[ipv6]:port` forms sent by
+/// Azure, IIS and CloudFront, and produced by `SocketAddr::to_string()`:
+///
+/// ```rust
+/// use real::ClientAddr;
+///
+/// let addr: ClientAddr = "[2001:db8::1]:443".parse().unwrap();
+/// assert_eq!(addr.ip, "2001:db8::1".parse::<std::net::IpAddr>().unwrap());
+/// assert_eq!(addr.port, Some(443));
+///
+/// let addr: ClientAddr = "203.0.113.5:51234".parse().unwrap();
+/// assert_eq!(addr.port, Some(51234));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientAddr {
+    /// The IP address.
+    pub ip: IpAddr,
+    /// The port, if the value carried one.
+    pub port: Option<u16>,
+}
+
+impl ClientAddr {
+    /// Create an address without a port.
+    pub fn new(ip: IpAddr) -> Self {
+        Self { ip, port: None }
+    }
+
+    /// The address as a socket address, if a port is known.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.port.map(|port| SocketAddr::new(self.ip, port))
+    }
+}
+
+impl FromStr for ClientAddr {
+    type Err = RealIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let invalid = || RealIpError::InvalidIpFormat(s.to_string());
+
+        // A bare address, including unbracketed IPv6
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Self::new(ip));
+        }
+
+        let (ip, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (v6, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            let ip = v6.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match rest {
+                "" => None,
+                _ => Some(rest.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (IpAddr::V6(ip), port)
+        } else {
+            // Unbracketed IPv6 already parsed above, so one colon separates the port
+            let (host, port) = s.split_once(':').ok_or_else(invalid)?;
+            (host.parse::<IpAddr>().map_err(|_| invalid())?, Some(port))
+        };
+
+        let port = port
+            .map(|port| port.parse::<u16>().map_err(|_| invalid()))
+            .transpose()?;
+        Ok(Self { ip, port })
+    }
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ip, self.port) {
+            (ip, None) => write!(f, "{}", ip),
+            (IpAddr::V4(ip), Some(port)) => write!(f, "{}:{}", ip, port),
+            (IpAddr::V6(ip), Some(port)) => write!(f, "[{}]:{}", ip, port),
+        }
+    }
+}
+
+impl From<IpAddr> for ClientAddr {
+    fn from(ip: IpAddr) -> Self {
+        Self::new(ip)
+    }
+}
+
+impl From<SocketAddr> for ClientAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: Some(addr.port()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forms() {
+        let cases = [
+            ("203.0.113.5", "203.0.113.5", None),
+            ("203.0.113.5:51234", "203.0.113.5", Some(51234)),
+            ("2001:db8::1", "2001:db8::1", None),
+            ("[2001:db8::1]", "2001:db8::1", None),
+            ("[2001:db8::1]:443", "2001:db8::1", Some(443)),
+        ];
+        for (input, ip, port) in cases {
+            let addr: ClientAddr = input.parse().unwrap();
+            assert_eq!(addr.ip, ip.parse::<IpAddr>().unwrap(), "{}", input);
+            assert_eq!(addr.port, port, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in [
+            "",
+            "203.0.113.5:",
+            "203.0.113.5:70000",
+            "[2001:db8::1",
+            "[1.2.3.4]:80",
+        ] {
+            assert!(input.parse::<ClientAddr>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_socket_addr_round_trip() {
+        let socket: SocketAddr = "[2001:db8::1]:8080".parse().unwrap();
+        let addr: ClientAddr = socket.to_string().parse().unwrap();
+        assert_eq!(addr.socket_addr(), Some(socket));
+        assert_eq!(addr.to_string(), socket.to_string());
+    }
+}
@@ -0,0 +1,360 @@
+/* src/cidr.rs */
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::{RealIpError, Result};
+
+/// An IP network in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// Host bits are cleared on construction, so `10.1.2.3/8` and `10.0.0.0/8`
+/// describe the same network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Create a network from an address and a prefix length.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = max_prefix(&addr);
+        if prefix > max {
+            return Err(RealIpError::InvalidCidr(format!("{}/{}", addr, prefix)));
+        }
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::from((u32::from(v4) & mask_v4(prefix)).to_be_bytes()),
+            IpAddr::V6(v6) => IpAddr::from((u128::from(v6) & mask_v6(prefix)).to_be_bytes()),
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// Create a network containing exactly one address.
+    pub fn host(addr: IpAddr) -> Self {
+        Self {
+            prefix: max_prefix(&addr),
+            addr,
+        }
+    }
+
+    /// The network address.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The prefix length in bits.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Check whether `ip` falls inside this network.
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are matched against IPv4 networks.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(ip) & mask_v4(self.prefix) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(ip) & mask_v6(self.prefix) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = RealIpError;
+
+    /// Parse `addr/prefix`, or a bare address as a single-host network.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let invalid = || RealIpError::InvalidCidr(s.to_string());
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix = prefix.parse::<u8>().map_err(|_| invalid())?;
+                Self::new(addr, prefix)
+            }
+            None => s.parse::<IpAddr>().map(Self::host).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        Self::host(addr)
+    }
+}
+
+/// A set of CIDR ranges, used to describe trusted proxies.
+#[derive(Clone, Default)]
+pub struct CidrSet {
+    ranges: Vec<Cidr>,
+    index: Option<Arc<RangeIndex>>,
+}
+
+/// The ranges of a set as sorted, merged address intervals, searched in
+/// logarithmic time.
+#[derive(Debug, Default)]
+struct RangeIndex {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl CidrSet {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a range to the set.
+    pub fn insert(&mut self, cidr: Cidr) {
+        if !self.ranges.contains(&cidr) {
+            self.ranges.push(cidr);
+            self.index = None;
+        }
+    }
+
+    /// Check whether `ip` falls inside any range of the set.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (&self.index, ip.to_canonical()) {
+            (Some(index), IpAddr::V4(ip)) => covers(&index.v4, u32::from(ip)),
+            (Some(index), IpAddr::V6(ip)) => covers(&index.v6, u128::from(ip)),
+            (None, _) => self.ranges.iter().any(|cidr| cidr.contains(ip)),
+        }
+    }
+
+    /// Index the ranges so [`contains`](Self::contains) no longer scans them
+    /// one by one. Inserting a range drops the index.
+    ///
+    /// [`IpExtractor::build`](crate::IpExtractor::build) indexes every set of
+    /// the configuration.
+    pub fn compile(&mut self) {
+        let mut index = RangeIndex::default();
+        for cidr in &self.ranges {
+            match cidr.addr {
+                IpAddr::V4(net) => {
+                    let start = u32::from(net);
+                    index.v4.push((start, start | !mask_v4(cidr.prefix)));
+                }
+                IpAddr::V6(net) => {
+                    let start = u128::from(net);
+                    index.v6.push((start, start | !mask_v6(cidr.prefix)));
+                }
+            }
+        }
+        merge(&mut index.v4);
+        merge(&mut index.v6);
+        self.index = Some(Arc::new(index));
+    }
+
+    /// Whether the set has been indexed by [`compile`](Self::compile).
+    pub fn is_compiled(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /// Number of ranges in the set.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Whether the set has no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Iterate over the ranges of the set.
+    pub fn iter(&self) -> impl Iterator<Item = &Cidr> {
+        self.ranges.iter()
+    }
+
+    /// Read ranges from a file, such as a downloaded provider list.
+    ///
+    /// Ranges are separated by commas or whitespace, and `#` starts a comment.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .map(str::parse::<Self>)
+            .try_fold(Self::new(), |mut set, ranges| {
+                set.extend(ranges?.ranges);
+                Ok(set)
+            })
+            .map_err(|err: RealIpError| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl fmt::Debug for CidrSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CidrSet")
+            .field("ranges", &self.ranges)
+            .finish()
+    }
+}
+
+/// Sets are equal when they hold the same ranges, whether indexed or not.
+impl PartialEq for CidrSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.ranges == other.ranges
+    }
+}
+
+impl Eq for CidrSet {}
+
+impl FromStr for CidrSet {
+    type Err = RealIpError;
+
+    /// Parse ranges separated by commas or whitespace, e.g. `"10.0.0.0/8, 192.168.0.0/16"`.
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(str::parse::<Cidr>)
+            .collect()
+    }
+}
+
+impl FromIterator<Cidr> for CidrSet {
+    fn from_iter<I: IntoIterator<Item = Cidr>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<Cidr> for CidrSet {
+    fn extend<I: IntoIterator<Item = Cidr>>(&mut self, iter: I) {
+        for cidr in iter {
+            self.insert(cidr);
+        }
+    }
+}
+
+impl From<Vec<Cidr>> for CidrSet {
+    fn from(ranges: Vec<Cidr>) -> Self {
+        ranges.into_iter().collect()
+    }
+}
+
+/// Sort inclusive intervals and merge the ones that overlap.
+fn merge<T: Copy + Ord>(intervals: &mut Vec<(T, T)>) {
+    intervals.sort_unstable();
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(intervals.len());
+    for &(start, end) in intervals.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *intervals = merged;
+}
+
+/// Whether one of the sorted, disjoint intervals contains `value`.
+fn covers<T: Copy + Ord>(intervals: &[(T, T)], value: T) -> bool {
+    let after = intervals.partition_point(|&(start, _)| start <= value);
+    after > 0 && value <= intervals[after - 1].1
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_contains() {
+        let cidr: Cidr = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert!(cidr.contains(&"10.255.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(cidr.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains(&"10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_cidr() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+        assert!("0.0.0.0/0".parse::<Cidr>().is_ok());
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let set: CidrSet = "10.0.0.0/8, 192.168.0.0/16\n::1".parse().unwrap();
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&"192.168.4.4".parse().unwrap()));
+        assert!(set.contains(&"::1".parse().unwrap()));
+        assert!(!set.contains(&"203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_compile() {
+        let mut set: CidrSet =
+            "10.0.0.0/9, 10.128.0.0/9, 10.1.0.0/16, 192.0.2.7, 2001:db8::/32, 0.0.0.0/1"
+                .parse()
+                .unwrap();
+        let plain = set.clone();
+        set.compile();
+        assert!(set.is_compiled());
+        assert_eq!(set, plain);
+        for ip in [
+            "10.0.0.0",
+            "10.255.255.255",
+            "11.0.0.0",
+            "127.255.255.255",
+            "128.0.0.0",
+            "192.0.2.6",
+            "192.0.2.7",
+            "192.0.2.8",
+            "::ffff:192.0.2.7",
+            "2001:db8::1",
+            "2001:db9::",
+            "::1",
+        ] {
+            let ip = ip.parse().unwrap();
+            assert_eq!(set.contains(&ip), plain.contains(&ip), "{}", ip);
+        }
+
+        set.insert("203.0.113.0/24".parse().unwrap());
+        assert!(!set.is_compiled());
+        assert!(set.contains(&"203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("real-cidr-{}.txt", std::process::id()));
+        std::fs::write(&path, "# proxies\n10.0.0.0/8\n2001:db8::/32 # lab\n\n").unwrap();
+        let set = CidrSet::from_file(&path).unwrap();
+        assert_eq!(set.len(), 2);
+
+        std::fs::write(&path, "10.0.0.0/33\n").unwrap();
+        assert!(CidrSet::from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,11 @@
+/* src/compat.rs */
+
+//! Extractors that reproduce the client address chosen by common proxy
+//! servers, configured from the same directives.
+//!
+//! Services behind the same edge can then share one source of truth with the
+//! proxy and never disagree about who the client is.
+
+pub mod apache;
+pub mod envoy;
+pub mod nginx;
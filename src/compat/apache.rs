@@ -0,0 +1,392 @@
+/* src/compat/apache.rs */
+
+//! Apache httpd's `mod_remoteip`.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use real::compat::apache::RemoteIpConfig;
+//! use std::collections::HashMap;
+//!
+//! let config = RemoteIpConfig::parse(r#"
+//!     RemoteIPHeader X-Forwarded-For
+//!     RemoteIPInternalProxy 10.0.0.0/8
+//!     RemoteIPTrustedProxy 198.51.100.0/24
+//! "#).unwrap();
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "203.0.113.9, 198.51.100.10".to_string());
+//!
+//! let ip = IpExtractor::apache_remoteip(&config).extract(&headers, Some("10.0.0.2".to_string()));
+//! assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+//! ```
+
+use std::net::IpAddr;
+
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::headers::HeaderSource;
+use crate::rule::{ChainEntry, ChainSelection, ChainSelector, HeaderRule};
+
+/// The `RemoteIP*` directives of an Apache configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteIpConfig {
+    /// `RemoteIPHeader`. Without it the module does nothing.
+    pub header: Option<String>,
+    /// `RemoteIPInternalProxy` and `RemoteIPInternalProxyList` ranges.
+    pub internal_proxies: CidrSet,
+    /// `RemoteIPTrustedProxy` and `RemoteIPTrustedProxyList` ranges.
+    pub trusted_proxies: CidrSet,
+    /// `RemoteIPProxiesHeader`.
+    pub proxies_header: Option<String>,
+}
+
+/// What mod_remoteip makes of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteIpRequest {
+    /// The client address, Apache's `useragent_ip`.
+    pub useragent_ip: IpAddr,
+    /// The new value of `RemoteIPHeader`: the entries left of the client, or
+    /// `None` when the header was consumed entirely or not sent.
+    pub remaining: Option<String>,
+    /// The trusted, not internal, proxies passed, closest to the client first.
+    /// Apache sends them in `RemoteIPProxiesHeader`.
+    pub proxies: Vec<IpAddr>,
+}
+
+impl RemoteIpConfig {
+    /// Read the `RemoteIP*` directives from an Apache configuration snippet.
+    ///
+    /// Sections such as `<VirtualHost>` are entered and other directives are
+    /// ignored. `RemoteIP*ProxyList` files are read relative to the current
+    /// directory. Host names are not resolved and fail the parse.
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (line, words) in directives(config)? {
+            let name = words[0].to_ascii_lowercase();
+            let args = &words[1..];
+            let invalid = |message: String| {
+                RealIpError::InvalidConfig(format!("line {}: {} {}", line, words[0], message))
+            };
+            let single = || match args {
+                [arg] => Ok(arg.clone()),
+                _ => Err(invalid("takes exactly one argument".to_string())),
+            };
+            let ranges = || -> Result<CidrSet> {
+                if args.is_empty() {
+                    return Err(invalid("takes at least one argument".to_string()));
+                }
+                args.iter()
+                    .map(|arg| {
+                        arg.parse()
+                            .map_err(|_| invalid(format!("has an invalid address {:?}", arg)))
+                    })
+                    .collect()
+            };
+            let list = || -> Result<CidrSet> {
+                let path = single()?;
+                CidrSet::from_file(&path).map_err(|err| invalid(format!("{}: {}", path, err)))
+            };
+            match name.as_str() {
+                "remoteipheader" => parsed.header = Some(single()?),
+                "remoteipproxiesheader" => parsed.proxies_header = Some(single()?),
+                "remoteipinternalproxy" => {
+                    parsed.internal_proxies.extend(ranges()?.iter().copied())
+                }
+                "remoteiptrustedproxy" => parsed.trusted_proxies.extend(ranges()?.iter().copied()),
+                "remoteipinternalproxylist" => {
+                    parsed.internal_proxies.extend(list()?.iter().copied())
+                }
+                "remoteiptrustedproxylist" => {
+                    parsed.trusted_proxies.extend(list()?.iter().copied())
+                }
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The header rules reproducing the configuration.
+    pub fn rules(&self) -> Vec<HeaderRule> {
+        let Some(header) = &self.header else {
+            return Vec::new();
+        };
+        let walk = RemoteIpWalk::new(self.internal_proxies.clone(), self.trusted_proxies.clone());
+        vec![HeaderRule::custom(header.clone(), parse_ips).selection(ChainSelection::custom(walk))]
+    }
+
+    /// Process a request from `peer` like mod_remoteip, including the header
+    /// rewrites.
+    pub fn resolve<H: HeaderSource + ?Sized>(&self, headers: &H, peer: IpAddr) -> RemoteIpRequest {
+        let value = self
+            .header
+            .as_ref()
+            .map(|header| headers.get_all(header).join(", "))
+            .filter(|value| !value.is_empty());
+        let Some(value) = value else {
+            return RemoteIpRequest {
+                useragent_ip: peer,
+                remaining: None,
+                proxies: Vec::new(),
+            };
+        };
+
+        let chain = parse_ips(&value);
+        let (selected, proxies) = walk(&chain, peer, &self.internal_proxies, &self.trusted_proxies);
+        let Some((index, addr)) = selected else {
+            return RemoteIpRequest {
+                useragent_ip: peer,
+                remaining: Some(value),
+                proxies,
+            };
+        };
+        let remaining = chain[..index]
+            .iter()
+            .map(|entry| match entry {
+                ChainEntry::Addr(addr) => addr.to_string(),
+                ChainEntry::Opaque(token) | ChainEntry::Invalid(token) => token.clone(),
+            })
+            .collect::<Vec<_>>();
+        RemoteIpRequest {
+            useragent_ip: addr.ip,
+            remaining: (!remaining.is_empty()).then(|| remaining.join(", ")),
+            proxies,
+        }
+    }
+}
+
+impl IpExtractor {
+    /// An extractor choosing the same address as mod_remoteip with `config`.
+    pub fn apache_remoteip(config: &RemoteIpConfig) -> Self {
+        Self::new()
+            .with_rules(config.rules())
+            .trust_private_ips(true)
+    }
+}
+
+/// mod_remoteip's walk: from the right while the hop that presented each
+/// address is an internal or trusted proxy. Only internal proxies may present
+/// private addresses, and once a trusted proxy is passed, internal ones are
+/// treated as trusted. With both sets empty, every hop is a trusted proxy.
+struct RemoteIpWalk {
+    internal: CidrSet,
+    trusted: CidrSet,
+}
+
+impl RemoteIpWalk {
+    fn new(mut internal: CidrSet, mut trusted: CidrSet) -> Self {
+        internal.compile();
+        trusted.compile();
+        Self { internal, trusted }
+    }
+}
+
+impl ChainSelector for RemoteIpWalk {
+    fn select(&self, chain: &[ChainEntry], peer: Option<IpAddr>) -> Option<(usize, ClientAddr)> {
+        walk(chain, peer?, &self.internal, &self.trusted).0
+    }
+}
+
+/// Walk a chain from the right the way mod_remoteip does, returning the client
+/// and the trusted proxies passed.
+fn walk(
+    chain: &[ChainEntry],
+    peer: IpAddr,
+    internal: &CidrSet,
+    trusted: &CidrSet,
+) -> (Option<(usize, ClientAddr)>, Vec<IpAddr>) {
+    let any = internal.is_empty() && trusted.is_empty();
+    let mut hop = peer;
+    let mut inside = !any;
+    let mut selected = None;
+    let mut proxies = Vec::new();
+
+    for (index, entry) in chain.iter().enumerate().rev() {
+        // An internal proxy may present a trusted one, but not the other way around
+        if !any && !internal.contains(&hop) {
+            if !trusted.contains(&hop) {
+                break;
+            }
+            inside = false;
+        }
+        let Some(addr) = entry.addr() else {
+            break;
+        };
+        if !inside && !is_global(&addr.ip) {
+            break;
+        }
+        if !inside {
+            proxies.push(hop);
+        }
+        selected = Some((index, addr));
+        hop = addr.ip;
+    }
+    proxies.reverse();
+    (selected, proxies)
+}
+
+/// Whether mod_remoteip accepts `ip` from a proxy that is not internal.
+fn is_global(ip: &IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(a == 10
+                || a == 127
+                || (a == 169 && b == 254)
+                || (a == 172 && b & 0xf0 == 16)
+                || (a == 192 && b == 168))
+        }
+        // Global unicast, 2000::/3
+        IpAddr::V6(v6) => v6.octets()[0] & 0xe0 == 0x20,
+    }
+}
+
+/// Split a header value on commas into bare addresses; ports are not accepted.
+fn parse_ips(value: &str) -> Vec<ChainEntry> {
+    value
+        .split(',')
+        .map(|token| token.trim_matches(' '))
+        .map(|token| match token.parse::<IpAddr>() {
+            Ok(ip) => ChainEntry::Addr(ip.into()),
+            Err(_) => ChainEntry::Invalid(token.to_string()),
+        })
+        .collect()
+}
+
+/// Split a configuration into directives with their line numbers, joining
+/// continuation lines and skipping comments and section tags.
+fn directives(config: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut directives = Vec::new();
+    let mut pending = String::new();
+    let mut start = 0;
+
+    for (number, line) in config.lines().enumerate() {
+        if pending.is_empty() {
+            start = number + 1;
+        }
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(line);
+        let logical = std::mem::take(&mut pending);
+        let logical = logical.trim();
+        if logical.is_empty() || logical.starts_with('#') || logical.starts_with('<') {
+            continue;
+        }
+        directives.push((start, words(logical, start)?));
+    }
+    Ok(directives)
+}
+
+/// Split a directive into words, honoring quotes like `ap_getword_conf`.
+fn words(line: &str, number: usize) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' || c == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some(end) if end == c => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(other) => word.push(other),
+                    None => {
+                        return Err(RealIpError::InvalidConfig(format!(
+                            "line {}: unterminated quoted string",
+                            number
+                        )));
+                    }
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const CONFIG: &str = r#"
+        <VirtualHost *:80>
+            # Load balancers, then a CDN
+            RemoteIPHeader X-Forwarded-For
+            RemoteIPInternalProxy 10.0.0.0/8 \
+                192.168.0.0/16
+            remoteiptrustedproxy "198.51.100.0/24"
+            RemoteIPProxiesHeader X-Forwarded-By
+        </VirtualHost>
+    "#;
+
+    fn resolve(forwarded_for: &str, peer: &str) -> RemoteIpRequest {
+        let config = RemoteIpConfig::parse(CONFIG).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("x-forwarded-for".to_string(), forwarded_for.to_string());
+
+        let resolved = config.resolve(&headers, peer.parse().unwrap());
+        let ip = IpExtractor::apache_remoteip(&config).extract(&headers, Some(peer.to_string()));
+        assert_eq!(ip, Some(resolved.useragent_ip));
+        resolved
+    }
+
+    #[test]
+    fn test_internal_and_trusted() {
+        let resolved = resolve(
+            "192.0.2.1, 203.0.113.9, 198.51.100.10, 192.168.1.1",
+            "10.0.0.2",
+        );
+        assert_eq!(resolved.useragent_ip.to_string(), "203.0.113.9");
+        assert_eq!(resolved.remaining.as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            resolved.proxies,
+            ["198.51.100.10".parse::<IpAddr>().unwrap()]
+        );
+
+        // Internal proxies may present private addresses, trusted ones may not
+        let resolved = resolve("10.9.9.9", "192.168.0.5");
+        assert_eq!(resolved.useragent_ip.to_string(), "10.9.9.9");
+        assert_eq!(resolved.remaining, None);
+        let resolved = resolve("10.9.9.9", "198.51.100.10");
+        assert_eq!(resolved.useragent_ip.to_string(), "198.51.100.10");
+        assert_eq!(resolved.remaining.as_deref(), Some("10.9.9.9"));
+
+        // Nor can a trusted proxy present an internal one
+        let resolved = resolve("10.9.9.9, 192.168.1.1, 198.51.100.10", "10.0.0.2");
+        assert_eq!(resolved.useragent_ip.to_string(), "198.51.100.10");
+
+        let resolved = resolve("203.0.113.9", "203.0.113.1");
+        assert_eq!(resolved.useragent_ip.to_string(), "203.0.113.1");
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = RemoteIpConfig::parse(CONFIG).unwrap();
+        assert_eq!(config.header.as_deref(), Some("X-Forwarded-For"));
+        assert_eq!(config.internal_proxies.len(), 2);
+        assert_eq!(config.proxies_header.as_deref(), Some("X-Forwarded-By"));
+        assert!(RemoteIpConfig::parse("").unwrap().rules().is_empty());
+
+        for invalid in [
+            "RemoteIPHeader",
+            "RemoteIPTrustedProxy proxy.example.com",
+            "RemoteIPInternalProxyList /nonexistent/proxies.txt",
+            "RemoteIPHeader \"X-Forwarded-For",
+        ] {
+            assert!(RemoteIpConfig::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,798 @@
+/* src/compat/envoy.rs */
+
+//! Envoy's original IP detection in the HTTP connection manager.
+//!
+//! The extractor is given the headers and peer Envoy would see, and chooses
+//! Envoy's trusted client address, so a service can stand where Envoy stands
+//! without disagreeing with the rest of the mesh.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use real::compat::envoy::EnvoyConfig;
+//! use std::collections::HashMap;
+//!
+//! let config = EnvoyConfig::from_yaml(r#"
+//!     stat_prefix: ingress_http
+//!     use_remote_address: true
+//!     xff_num_trusted_hops: 2
+//! "#).unwrap();
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "203.0.113.128, 203.0.113.10, 203.0.113.1".to_string());
+//!
+//! let peer = "192.0.2.5".parse().unwrap();
+//! assert_eq!(config.detect(&headers, peer).unwrap().external_address, Some("203.0.113.10".parse().unwrap()));
+//!
+//! let ip = IpExtractor::envoy(&config).extract(&headers, Some("192.0.2.5".to_string()));
+//! assert_eq!(ip, Some("203.0.113.10".parse().unwrap()));
+//! ```
+
+use std::net::IpAddr;
+
+use crate::addr::ClientAddr;
+use crate::cidr::{Cidr, CidrSet};
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::headers::HeaderSource;
+use crate::rule::{ChainEntry, ChainSelection, ChainSelector, HeaderRule};
+
+/// Ranges Envoy treats as internal when `internal_address_config` is not set.
+pub const DEFAULT_INTERNAL_RANGES: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "127.0.0.0/8",
+    "::1/128",
+    "fd00::/8",
+];
+
+/// The header Envoy reads and appends to.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// An `original_ip_detection_extensions` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpDetection {
+    /// `envoy.extensions.http.original_ip_detection.xff.v3.XffConfig`.
+    Xff {
+        /// Proxies in front of Envoy that append to `X-Forwarded-For`.
+        xff_num_trusted_hops: u32,
+    },
+    /// `envoy.extensions.http.original_ip_detection.custom_header.v3.CustomHeaderConfig`.
+    CustomHeader {
+        /// Header holding the client address.
+        header_name: String,
+        /// Whether the address may mark the request as internal.
+        allow_extension_to_set_address_as_trusted: bool,
+        /// The status code to reject requests without a valid address with.
+        reject_with_status: Option<String>,
+    },
+}
+
+/// The original IP detection settings of an `HttpConnectionManager`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvoyConfig {
+    /// `use_remote_address`.
+    pub use_remote_address: bool,
+    /// `xff_num_trusted_hops`.
+    pub xff_num_trusted_hops: u32,
+    /// `original_ip_detection_extensions`, only used without `use_remote_address`.
+    pub original_ip_detection_extensions: Vec<IpDetection>,
+    /// `internal_address_config.cidr_ranges`, or [`DEFAULT_INTERNAL_RANGES`] when unset.
+    pub internal_address_config: Option<CidrSet>,
+}
+
+/// The address Envoy settles on for a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OriginalIp {
+    /// The trusted client address, Envoy's downstream remote address.
+    pub address: IpAddr,
+    /// Whether the request is internal, marked with `x-envoy-internal`.
+    pub internal: bool,
+    /// The value of `x-envoy-external-address`, set by edge proxies with
+    /// `use_remote_address` for requests that are not internal.
+    pub external_address: Option<IpAddr>,
+}
+
+impl EnvoyConfig {
+    /// Read the settings from an `HttpConnectionManager` in YAML.
+    ///
+    /// Only block mappings and sequences, flow mappings and plain or quoted
+    /// scalars are understood. The connection manager may be nested, such as
+    /// inside a listener; the first mapping with its keys is used.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let doc = yaml::parse(yaml)?;
+        let invalid = |message: String| RealIpError::InvalidConfig(message);
+        let Some(hcm) = doc.find(&|map| {
+            [
+                "use_remote_address",
+                "xff_num_trusted_hops",
+                "original_ip_detection_extensions",
+                "internal_address_config",
+                "stat_prefix",
+            ]
+            .iter()
+            .any(|key| map.get(key).is_some())
+        }) else {
+            return Ok(Self::default());
+        };
+
+        let mut config = Self {
+            use_remote_address: hcm.bool("use_remote_address")?.unwrap_or(false),
+            xff_num_trusted_hops: hcm.u32("xff_num_trusted_hops")?.unwrap_or(0),
+            ..Self::default()
+        };
+        for extension in hcm.list("original_ip_detection_extensions")? {
+            let typed = extension
+                .get("typed_config")
+                .ok_or_else(|| invalid("extension without typed_config".to_string()))?;
+            let kind = typed.str("@type")?.unwrap_or_default();
+            let detection = if kind.ends_with(".CustomHeaderConfig") {
+                IpDetection::CustomHeader {
+                    header_name: typed
+                        .str("header_name")?
+                        .ok_or_else(|| invalid("custom header without header_name".to_string()))?
+                        .to_ascii_lowercase(),
+                    allow_extension_to_set_address_as_trusted: typed
+                        .bool("allow_extension_to_set_address_as_trusted")?
+                        .unwrap_or(false),
+                    reject_with_status: match typed.get("reject_with_status") {
+                        Some(status) => Some(
+                            status
+                                .str("code")?
+                                .ok_or_else(|| invalid("reject_with_status without code".into()))?
+                                .to_string(),
+                        ),
+                        None => None,
+                    },
+                }
+            } else if kind.ends_with(".XffConfig") {
+                IpDetection::Xff {
+                    xff_num_trusted_hops: typed.u32("xff_num_trusted_hops")?.unwrap_or(0),
+                }
+            } else {
+                return Err(invalid(format!("unsupported extension {:?}", kind)));
+            };
+            config.original_ip_detection_extensions.push(detection);
+        }
+        if let Some(internal) = hcm.get("internal_address_config") {
+            let mut ranges = CidrSet::new();
+            for range in internal.list("cidr_ranges")? {
+                let prefix = range.str("address_prefix")?.unwrap_or_default();
+                let len = range.u32("prefix_len")?.unwrap_or(0);
+                ranges.insert(format!("{}/{}", prefix, len).parse()?);
+            }
+            config.internal_address_config = Some(ranges);
+        }
+
+        if !config.original_ip_detection_extensions.is_empty() && config.xff_num_trusted_hops > 0 {
+            return Err(invalid(
+                "original IP detection extensions and xff_num_trusted_hops may not be mixed"
+                    .to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Detect the client of a request from `peer`, the way Envoy does.
+    ///
+    /// Fails with [`RealIpError::Rejected`] where a custom header extension
+    /// with `reject_with_status` would reject the request.
+    pub fn detect<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        peer: IpAddr,
+    ) -> Result<OriginalIp> {
+        let values = headers.get_all(FORWARDED_FOR);
+        let xff = (!values.is_empty()).then(|| values.join(","));
+
+        if self.use_remote_address {
+            let address = self
+                .xff_num_trusted_hops
+                .checked_sub(1)
+                .and_then(|skip| last_address(xff.as_deref(), skip))
+                .map_or(peer, |(address, _)| address);
+            // Only a request without X-Forwarded-For can be internal
+            let internal = xff.is_none() && self.is_internal(&address);
+            return Ok(OriginalIp {
+                address,
+                internal,
+                external_address: (!internal).then_some(address),
+            });
+        }
+
+        let default = [IpDetection::Xff {
+            xff_num_trusted_hops: self.xff_num_trusted_hops,
+        }];
+        let extensions = match self.original_ip_detection_extensions.as_slice() {
+            [] => &default[..],
+            extensions => extensions,
+        };
+        for extension in extensions {
+            let detected = match extension {
+                IpDetection::Xff {
+                    xff_num_trusted_hops,
+                } => last_address(xff.as_deref(), *xff_num_trusted_hops),
+                IpDetection::CustomHeader {
+                    header_name,
+                    allow_extension_to_set_address_as_trusted,
+                    reject_with_status,
+                } => {
+                    match headers
+                        .get(header_name)
+                        .and_then(|value| value.parse().ok())
+                    {
+                        Some(address) => {
+                            Some((address, *allow_extension_to_set_address_as_trusted))
+                        }
+                        None => match reject_with_status {
+                            Some(status) => {
+                                return Err(RealIpError::Rejected {
+                                    header: header_name.clone(),
+                                    status: status.clone(),
+                                });
+                            }
+                            None => None,
+                        },
+                    }
+                }
+            };
+            if let Some((address, trusted)) = detected {
+                return Ok(OriginalIp {
+                    address,
+                    internal: trusted && self.is_internal(&address),
+                    external_address: None,
+                });
+            }
+        }
+        Ok(OriginalIp {
+            address: peer,
+            internal: false,
+            external_address: None,
+        })
+    }
+
+    /// The header rules reproducing the trusted client address.
+    ///
+    /// Where a custom header extension would reject the request, the next
+    /// extension is tried instead; use [`detect`](Self::detect) to reject.
+    pub fn rules(&self) -> Vec<HeaderRule> {
+        let xff = |hops: u32| {
+            HeaderRule::custom(FORWARDED_FOR, parse_xff)
+                .selection(ChainSelection::custom(XffHops(hops as usize)))
+        };
+        if self.use_remote_address {
+            return match self.xff_num_trusted_hops {
+                0 => Vec::new(),
+                hops => vec![xff(hops)],
+            };
+        }
+        if self.original_ip_detection_extensions.is_empty() {
+            return vec![xff(self.xff_num_trusted_hops + 1)];
+        }
+        self.original_ip_detection_extensions
+            .iter()
+            .map(|extension| match extension {
+                IpDetection::Xff {
+                    xff_num_trusted_hops,
+                } => xff(xff_num_trusted_hops + 1),
+                IpDetection::CustomHeader { header_name, .. } => {
+                    HeaderRule::custom(header_name.clone(), parse_ip)
+                        .first_only()
+                        .selection(ChainSelection::custom(XffHops(1)))
+                }
+            })
+            .collect()
+    }
+
+    fn is_internal(&self, address: &IpAddr) -> bool {
+        match &self.internal_address_config {
+            Some(ranges) => ranges.contains(address),
+            None => DEFAULT_INTERNAL_RANGES.iter().any(|range| {
+                range
+                    .parse::<Cidr>()
+                    .is_ok_and(|cidr| cidr.contains(address))
+            }),
+        }
+    }
+}
+
+/// The n-th address from the right like [`ChainSelection::TrustedHops`], but
+/// as in Envoy, a shorter chain or an entry that is not an address selects
+/// nothing, so the socket peer is used.
+struct XffHops(usize);
+
+impl ChainSelector for XffHops {
+    fn select(&self, chain: &[ChainEntry], _: Option<IpAddr>) -> Option<(usize, ClientAddr)> {
+        match chain.len().checked_sub(self.0) {
+            Some(index) if self.0 > 0 => chain[index].addr().map(|addr| (index, addr)),
+            _ => None,
+        }
+    }
+}
+
+impl IpExtractor {
+    /// An extractor choosing the same trusted client address as Envoy with `config`.
+    pub fn envoy(config: &EnvoyConfig) -> Self {
+        Self::new()
+            .with_rules(config.rules())
+            .trust_private_ips(true)
+    }
+}
+
+/// Envoy's `getLastAddressFromXFF`: the address after skipping `skip` entries
+/// from the right, and whether the header held only that entry, so its
+/// address may be checked for being internal. Skipped entries are not validated.
+fn last_address(xff: Option<&str>, skip: u32) -> Option<(IpAddr, bool)> {
+    let mut xff = xff?;
+    for _ in 0..skip {
+        xff = &xff[..xff.rfind(',')?];
+    }
+    let last_comma = xff.rfind(',');
+    if let Some(comma) = last_comma
+        && comma + 1 < xff.len()
+    {
+        xff = &xff[comma + 1..];
+    }
+    // Only an untouched single-entry header may be trusted
+    let single = last_comma.is_none() && skip == 0;
+    let address = xff.trim_matches([' ', '\t']).parse().ok()?;
+    Some((address, single))
+}
+
+/// Split `X-Forwarded-For` into bare addresses, like Envoy.
+fn parse_xff(value: &str) -> Vec<ChainEntry> {
+    value
+        .split(',')
+        .flat_map(|token| parse_ip(token.trim_matches([' ', '\t'])))
+        .collect()
+}
+
+/// A header holding exactly one bare address.
+fn parse_ip(value: &str) -> Vec<ChainEntry> {
+    vec![match value.parse::<IpAddr>() {
+        Ok(ip) => ChainEntry::Addr(ip.into()),
+        Err(_) => ChainEntry::Invalid(value.to_string()),
+    }]
+}
+
+/// The small subset of YAML found in Envoy configurations.
+mod yaml {
+    use crate::error::{RealIpError, Result};
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub(super) enum Value {
+        Scalar(String),
+        Map(Vec<(String, Value)>),
+        List(Vec<Value>),
+    }
+
+    impl Value {
+        pub(super) fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Self::Map(entries) => entries
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        /// The first mapping, in document order, matching `predicate`.
+        pub(super) fn find(&self, predicate: &dyn Fn(&Value) -> bool) -> Option<&Value> {
+            if matches!(self, Self::Map(_)) && predicate(self) {
+                return Some(self);
+            }
+            match self {
+                Self::Scalar(_) => None,
+                Self::Map(entries) => entries.iter().find_map(|(_, value)| value.find(predicate)),
+                Self::List(items) => items.iter().find_map(|item| item.find(predicate)),
+            }
+        }
+
+        pub(super) fn str(&self, key: &str) -> Result<Option<&str>> {
+            match self.get(key) {
+                None => Ok(None),
+                Some(Self::Scalar(value)) => Ok(Some(value)),
+                Some(_) => Err(invalid(format!("{} is not a scalar", key))),
+            }
+        }
+
+        /// A boolean, possibly wrapped like `BoolValue` as `{value: true}`.
+        pub(super) fn bool(&self, key: &str) -> Result<Option<bool>> {
+            let value = match self.get(key) {
+                Some(wrapper @ Self::Map(_)) => wrapper.str("value")?,
+                _ => self.str(key)?,
+            };
+            value
+                .map(|value| match value {
+                    "true" => Ok(true),
+                    "false" => Ok(false),
+                    _ => Err(invalid(format!("{} is not a boolean", key))),
+                })
+                .transpose()
+        }
+
+        pub(super) fn u32(&self, key: &str) -> Result<Option<u32>> {
+            self.str(key)?
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| invalid(format!("{} is not an integer", key)))
+                })
+                .transpose()
+        }
+
+        pub(super) fn list(&self, key: &str) -> Result<&[Value]> {
+            match self.get(key) {
+                None => Ok(&[]),
+                Some(Self::List(items)) => Ok(items),
+                Some(_) => Err(invalid(format!("{} is not a list", key))),
+            }
+        }
+    }
+
+    fn invalid(message: String) -> RealIpError {
+        RealIpError::InvalidConfig(message)
+    }
+
+    struct Line {
+        indent: usize,
+        text: String,
+        number: usize,
+    }
+
+    pub(super) fn parse(text: &str) -> Result<Value> {
+        let mut lines: Vec<Line> = text
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let line = strip_comment(line).trim_end();
+                let text = line.trim_start();
+                (!text.is_empty() && text != "---").then(|| Line {
+                    indent: line.len() - text.len(),
+                    text: text.to_string(),
+                    number: index + 1,
+                })
+            })
+            .collect();
+        let Some(indent) = lines.first().map(|line| line.indent) else {
+            return Ok(Value::Map(Vec::new()));
+        };
+        let mut pos = 0;
+        let value = block(&mut lines, &mut pos, indent)?;
+        match lines.get(pos) {
+            Some(line) => Err(invalid(format!(
+                "line {}: unexpected indentation",
+                line.number
+            ))),
+            None => Ok(value),
+        }
+    }
+
+    fn strip_comment(line: &str) -> &str {
+        let mut quote = None;
+        let mut previous = ' ';
+        for (index, c) in line.char_indices() {
+            match (quote, c) {
+                (None, '#') if previous.is_whitespace() => return &line[..index],
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), _) if c == open => quote = None,
+                _ => {}
+            }
+            previous = c;
+        }
+        line
+    }
+
+    fn is_item(text: &str) -> bool {
+        text == "-" || text.starts_with("- ")
+    }
+
+    fn block(lines: &mut [Line], pos: &mut usize, indent: usize) -> Result<Value> {
+        if is_item(&lines[*pos].text) {
+            let mut items = Vec::new();
+            while let Some(line) = lines.get_mut(*pos)
+                && line.indent == indent
+                && is_item(&line.text)
+            {
+                let rest = line.text[1..].trim_start().to_string();
+                if rest.is_empty() {
+                    *pos += 1;
+                    items.push(nested(lines, pos, indent)?);
+                } else {
+                    // Parse the rest of the line as if it started a block of its own
+                    let offset = line.text.len() - rest.len();
+                    line.indent += offset;
+                    line.text = rest;
+                    items.push(block(lines, pos, indent + offset)?);
+                }
+            }
+            return Ok(Value::List(items));
+        }
+
+        let mut entries = Vec::new();
+        while let Some(line) = lines.get(*pos)
+            && line.indent == indent
+            && !is_item(&line.text)
+        {
+            let number = line.number;
+            let (key, value) = split_key(&line.text)
+                .ok_or_else(|| invalid(format!("line {}: expected a key", number)))?;
+            *pos += 1;
+            let value = match value {
+                "" => match lines.get(*pos) {
+                    // A list may sit at the same indentation as its key
+                    Some(next) if next.indent == indent && is_item(&next.text) => {
+                        block(lines, pos, indent)?
+                    }
+                    _ => nested(lines, pos, indent)?,
+                },
+                value => flow(value).map_err(|err| invalid(format!("line {}: {}", number, err)))?,
+            };
+            entries.push((key, value));
+        }
+        Ok(Value::Map(entries))
+    }
+
+    /// The block indented below the current line, or an empty value.
+    fn nested(lines: &mut [Line], pos: &mut usize, indent: usize) -> Result<Value> {
+        match lines.get(*pos) {
+            Some(next) if next.indent > indent => {
+                let indent = next.indent;
+                block(lines, pos, indent)
+            }
+            _ => Ok(Value::Scalar(String::new())),
+        }
+    }
+
+    fn split_key(text: &str) -> Option<(String, &str)> {
+        let (key, rest) = match text.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = text[1..].find(quote)? + 1;
+                (text[1..end].to_string(), &text[end + 1..])
+            }
+            _ => {
+                let colon = text
+                    .find(": ")
+                    .or_else(|| text.strip_suffix(':').map(str::len))?;
+                (text[..colon].trim_end().to_string(), &text[colon..])
+            }
+        };
+        let value = rest.trim_start().strip_prefix(':')?;
+        Some((key, value.trim()))
+    }
+
+    /// Parse an inline value: a flow mapping or sequence, or a scalar.
+    fn flow(text: &str) -> std::result::Result<Value, String> {
+        if !text.starts_with(['{', '[']) {
+            return Ok(Value::Scalar(unquote(text)));
+        }
+        let mut chars = text.chars().peekable();
+        let value = flow_value(&mut chars)?;
+        match chars.find(|c| !c.is_whitespace()) {
+            Some(c) => Err(format!("unexpected {:?}", c)),
+            None => Ok(value),
+        }
+    }
+
+    fn flow_value(
+        chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    ) -> std::result::Result<Value, String> {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let close = match chars.peek() {
+            Some('{') => '}',
+            Some('[') => ']',
+            _ => {
+                let mut scalar = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}' | ']' | ':')) {
+                    scalar.push(c);
+                }
+                return Ok(Value::Scalar(unquote(scalar.trim())));
+            }
+        };
+        chars.next();
+        let mut entries = Vec::new();
+        let mut items = Vec::new();
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+            if chars.next_if_eq(&close).is_some() {
+                break;
+            }
+            if chars.peek().is_none() {
+                return Err(format!("missing {:?}", close));
+            }
+            let value = flow_value(chars)?;
+            if close == '}' {
+                let Value::Scalar(key) = value else {
+                    return Err("expected a key".to_string());
+                };
+                if chars.next_if_eq(&':').is_none() {
+                    return Err(format!("expected \":\" after {:?}", key));
+                }
+                entries.push((key, flow_value(chars)?));
+            } else {
+                items.push(value);
+            }
+        }
+        Ok(match close {
+            '}' => Value::Map(entries),
+            _ => Value::List(items),
+        })
+    }
+
+    fn unquote(text: &str) -> String {
+        for quote in ['"', '\''] {
+            if let Some(inner) = text
+                .strip_prefix(quote)
+                .and_then(|text| text.strip_suffix(quote))
+            {
+                return inner.to_string();
+            }
+        }
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const CHAIN: &str = "203.0.113.128, 203.0.113.10, 203.0.113.1";
+
+    fn detect(config: &EnvoyConfig, xff: Option<&str>, peer: &str) -> OriginalIp {
+        let mut headers = HashMap::new();
+        if let Some(xff) = xff {
+            headers.insert("x-forwarded-for".to_string(), xff.to_string());
+        }
+        let detected = config.detect(&headers, peer.parse().unwrap()).unwrap();
+        let ip = IpExtractor::envoy(config).extract(&headers, Some(peer.to_string()));
+        assert_eq!(ip, Some(detected.address));
+        detected
+    }
+
+    #[test]
+    fn test_documented_examples() {
+        let edge = |hops| EnvoyConfig {
+            use_remote_address: true,
+            xff_num_trusted_hops: hops,
+            ..EnvoyConfig::default()
+        };
+        let internal = |hops| EnvoyConfig {
+            xff_num_trusted_hops: hops,
+            ..EnvoyConfig::default()
+        };
+
+        let detected = detect(&edge(0), Some(CHAIN), "192.0.2.5");
+        assert_eq!(
+            detected.external_address,
+            Some("192.0.2.5".parse().unwrap())
+        );
+        let detected = detect(
+            &internal(0),
+            Some(&format!("{}, 192.0.2.5", CHAIN)),
+            "10.11.12.13",
+        );
+        assert_eq!(detected.address.to_string(), "192.0.2.5");
+        assert_eq!(detected.external_address, None);
+        let detected = detect(&edge(2), Some(CHAIN), "192.0.2.5");
+        assert_eq!(
+            detected.external_address,
+            Some("203.0.113.10".parse().unwrap())
+        );
+        let detected = detect(
+            &internal(2),
+            Some(&format!("{}, 192.0.2.5", CHAIN)),
+            "10.11.12.13",
+        );
+        assert_eq!(detected.address.to_string(), "203.0.113.10");
+
+        // Too few entries, or a bad one: the downstream address
+        assert_eq!(
+            detect(&edge(5), Some(CHAIN), "192.0.2.5")
+                .address
+                .to_string(),
+            "192.0.2.5"
+        );
+        assert_eq!(
+            detect(&edge(1), Some("1.2.3.4, bad"), "192.0.2.5")
+                .address
+                .to_string(),
+            "192.0.2.5"
+        );
+
+        // An address reached by skipping hops is never internal
+        let detected = detect(&internal(1), Some("10.0.0.1, 192.0.2.5"), "10.11.12.13");
+        assert_eq!(detected.address.to_string(), "10.0.0.1");
+        assert!(!detected.internal);
+        assert!(detect(&internal(0), Some("10.0.0.1"), "10.11.12.13").internal);
+
+        // Internal only without X-Forwarded-For
+        let detected = detect(&edge(0), None, "10.0.0.1");
+        assert!(detected.internal);
+        assert_eq!(detected.external_address, None);
+    }
+
+    #[test]
+    fn test_repeated_custom_header() {
+        let config = EnvoyConfig {
+            original_ip_detection_extensions: vec![IpDetection::CustomHeader {
+                header_name: "x-real-ip".to_string(),
+                allow_extension_to_set_address_as_trusted: false,
+                reject_with_status: None,
+            }],
+            ..EnvoyConfig::default()
+        };
+        let peer = "10.0.0.2";
+
+        // Like Envoy, only the first line of the header is read
+        let headers = vec![
+            ("X-Real-IP".to_string(), "203.0.113.7".to_string()),
+            ("X-Real-IP".to_string(), "198.51.100.9".to_string()),
+        ];
+        let detected = config.detect(&headers, peer.parse().unwrap()).unwrap();
+        assert_eq!(detected.address, "203.0.113.7".parse::<IpAddr>().unwrap());
+        let ip = IpExtractor::envoy(&config).extract(&headers, Some(peer.to_string()));
+        assert_eq!(ip, Some(detected.address));
+
+        let headers = vec![
+            ("X-Real-IP".to_string(), "nope".to_string()),
+            ("X-Real-IP".to_string(), "198.51.100.9".to_string()),
+        ];
+        let detected = config.detect(&headers, peer.parse().unwrap()).unwrap();
+        assert_eq!(detected.address, peer.parse::<IpAddr>().unwrap());
+        let ip = IpExtractor::envoy(&config).extract(&headers, Some(peer.to_string()));
+        assert_eq!(ip, Some(detected.address));
+    }
+
+    #[test]
+    fn test_from_yaml() {
+        let config = EnvoyConfig::from_yaml(
+            r#"
+filter_chains:
+- filters:
+  - name: envoy.filters.network.http_connection_manager
+    typed_config:
+      "@type": type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager
+      stat_prefix: ingress_http  # comment
+      use_remote_address: false
+      original_ip_detection_extensions:
+      - name: envoy.extensions.http.original_ip_detection.custom_header
+        typed_config:
+          "@type": type.googleapis.com/envoy.extensions.http.original_ip_detection.custom_header.v3.CustomHeaderConfig
+          header_name: X-Real-IP
+          allow_extension_to_set_address_as_trusted: true
+          reject_with_status: { code: Forbidden }
+      internal_address_config:
+        cidr_ranges:
+        - address_prefix: 10.0.0.0
+          prefix_len: 8
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.original_ip_detection_extensions,
+            [IpDetection::CustomHeader {
+                header_name: "x-real-ip".to_string(),
+                allow_extension_to_set_address_as_trusted: true,
+                reject_with_status: Some("Forbidden".to_string()),
+            }]
+        );
+        assert_eq!(config.internal_address_config.as_ref().unwrap().len(), 1);
+
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "10.1.2.3".to_string());
+        let detected = config
+            .detect(&headers, "10.0.0.2".parse().unwrap())
+            .unwrap();
+        assert!(detected.internal);
+        headers.insert("x-real-ip".to_string(), "nope".to_string());
+        assert!(matches!(
+            config.detect(&headers, "10.0.0.2".parse().unwrap()),
+            Err(RealIpError::Rejected { .. })
+        ));
+
+        assert!(EnvoyConfig::from_yaml("xff_num_trusted_hops: many").is_err());
+        assert!(EnvoyConfig::from_yaml("a:\n  b: 1\n c: 2").is_err());
+    }
+}
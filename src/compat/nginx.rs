@@ -0,0 +1,402 @@
+/* src/compat/nginx.rs */
+
+//! nginx's `ngx_http_realip_module`.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use real::compat::nginx::RealIpConfig;
+//! use std::collections::HashMap;
+//!
+//! let config = RealIpConfig::parse(r#"
+//!     set_real_ip_from 10.0.0.0/8;
+//!     real_ip_header   X-Forwarded-For;
+//!     real_ip_recursive on;
+//! "#).unwrap();
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "198.51.100.7, 10.0.3.1".to_string());
+//!
+//! let ip = IpExtractor::nginx_realip(&config).extract(&headers, Some("10.0.0.2".to_string()));
+//! assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+//! ```
+
+use std::net::IpAddr;
+
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainEntry, ChainSelection, ChainSelector, HeaderRule};
+
+/// Where `real_ip_header` takes the address from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RealIpHeader {
+    /// A request header, such as `X-Real-IP` or `X-Forwarded-For`.
+    Header(String),
+    /// The source address of the PROXY protocol header.
+    ProxyProtocol,
+}
+
+/// The realip directives of an nginx configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealIpConfig {
+    /// `set_real_ip_from` addresses and ranges.
+    pub set_real_ip_from: CidrSet,
+    /// Whether `set_real_ip_from unix:` was given. Peers without an address are
+    /// never trusted by the extractor, so this is informational.
+    pub unix: bool,
+    /// `real_ip_header`, `X-Real-IP` by default.
+    pub real_ip_header: RealIpHeader,
+    /// `real_ip_recursive`, off by default.
+    pub real_ip_recursive: bool,
+}
+
+impl Default for RealIpConfig {
+    fn default() -> Self {
+        Self {
+            set_real_ip_from: CidrSet::new(),
+            unix: false,
+            real_ip_header: RealIpHeader::Header("X-Real-IP".to_string()),
+            real_ip_recursive: false,
+        }
+    }
+}
+
+impl RealIpConfig {
+    /// Read the realip directives from an nginx configuration snippet.
+    ///
+    /// Blocks such as `http` and `server` are entered and other directives are
+    /// ignored. Later directives override earlier ones. Host names in
+    /// `set_real_ip_from` are not resolved and fail the parse.
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (line, words) in directives(config)? {
+            let invalid = |message: &str| {
+                RealIpError::InvalidConfig(format!("line {}: {} {}", line, words[0], message))
+            };
+            let arg = match &words[1..] {
+                [arg] => arg.as_str(),
+                _ if is_realip_directive(&words[0]) => {
+                    return Err(invalid("takes exactly one argument"));
+                }
+                _ => continue,
+            };
+            match words[0].as_str() {
+                "set_real_ip_from" if arg == "unix:" => parsed.unix = true,
+                "set_real_ip_from" => {
+                    let cidr = arg
+                        .parse()
+                        .map_err(|_| invalid(&format!("has an invalid address {:?}", arg)))?;
+                    parsed.set_real_ip_from.insert(cidr);
+                }
+                "real_ip_header" if arg == "proxy_protocol" => {
+                    parsed.real_ip_header = RealIpHeader::ProxyProtocol
+                }
+                "real_ip_header" => parsed.real_ip_header = RealIpHeader::Header(arg.to_string()),
+                "real_ip_recursive" => {
+                    parsed.real_ip_recursive = match arg {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(invalid("must be on or off")),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The header rules reproducing the configuration.
+    ///
+    /// nginx reads any `real_ip_header` as a list separated by commas or
+    /// spaces, combining repeated lines for `X-Forwarded-For` only. With
+    /// `proxy_protocol` there are no rules: pass the address from
+    /// [`proxy_protocol_client`](Self::proxy_protocol_client) as the fallback,
+    /// so the PROXY source is only used from `set_real_ip_from` peers.
+    pub fn rules(&self) -> Vec<HeaderRule> {
+        match &self.real_ip_header {
+            RealIpHeader::Header(name) => {
+                let rule = HeaderRule::custom(name.clone(), parse_tokens)
+                    .trusted_peers(self.set_real_ip_from.clone())
+                    .selection(ChainSelection::custom(RealIpWalk::new(
+                        self.set_real_ip_from.clone(),
+                        self.real_ip_recursive,
+                    )));
+                // Only X-Forwarded-For lines are combined; other headers use the first line
+                if name.eq_ignore_ascii_case("x-forwarded-for") {
+                    vec![rule]
+                } else {
+                    vec![rule.first_only()]
+                }
+            }
+            RealIpHeader::ProxyProtocol => Vec::new(),
+        }
+    }
+
+    /// The client address nginx uses for a connection from `peer` that sent a
+    /// PROXY header with `source`, such as the `peer` and `source()` of a
+    /// `ProxiedAddr`.
+    ///
+    /// The source is only used with `real_ip_header proxy_protocol` and when
+    /// `peer` is in `set_real_ip_from`; otherwise the peer is the client.
+    pub fn proxy_protocol_client(&self, peer: IpAddr, source: Option<IpAddr>) -> IpAddr {
+        match source {
+            Some(source)
+                if self.real_ip_header == RealIpHeader::ProxyProtocol
+                    && self.set_real_ip_from.contains(&peer) =>
+            {
+                source
+            }
+            _ => peer,
+        }
+    }
+}
+
+impl IpExtractor {
+    /// An extractor choosing the same address as nginx's realip module with `config`.
+    ///
+    /// Like nginx, private addresses from headers are accepted.
+    pub fn nginx_realip(config: &RealIpConfig) -> Self {
+        Self::new()
+            .with_rules(config.rules())
+            .trust_private_ips(true)
+    }
+}
+
+/// nginx's walk: the rightmost address, and with `recursive`, the walk
+/// continues left while the address is trusted. A token that is not an address
+/// ends the walk at the last address found, and if every address is trusted
+/// the leftmost one is used.
+struct RealIpWalk {
+    trusted: CidrSet,
+    recursive: bool,
+}
+
+impl RealIpWalk {
+    fn new(mut trusted: CidrSet, recursive: bool) -> Self {
+        trusted.compile();
+        Self { trusted, recursive }
+    }
+}
+
+impl ChainSelector for RealIpWalk {
+    fn select(&self, chain: &[ChainEntry], _: Option<IpAddr>) -> Option<(usize, ClientAddr)> {
+        let mut candidate = None;
+        for (index, entry) in chain.iter().enumerate().rev() {
+            let Some(addr) = entry.addr() else {
+                break;
+            };
+            candidate = Some((index, addr));
+            if !self.recursive || !self.trusted.contains(&addr.ip) {
+                break;
+            }
+        }
+        candidate
+    }
+}
+
+fn is_realip_directive(name: &str) -> bool {
+    matches!(
+        name,
+        "set_real_ip_from" | "real_ip_header" | "real_ip_recursive"
+    )
+}
+
+/// Split a header value on commas and spaces, like `ngx_http_get_forwarded_addr`.
+fn parse_tokens(value: &str) -> Vec<ChainEntry> {
+    value
+        .split([',', ' '])
+        .filter(|token| !token.is_empty())
+        .map(|token| match token.parse::<ClientAddr>() {
+            Ok(addr) => ChainEntry::Addr(addr),
+            Err(_) => ChainEntry::Invalid(token.to_string()),
+        })
+        .collect()
+}
+
+/// Split a configuration into simple directives with their line numbers.
+/// Block directives are dropped and their contents kept.
+fn directives(config: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let unexpected = |line: usize, what: &str| {
+        RealIpError::InvalidConfig(format!("line {}: unexpected {}", line, what))
+    };
+    let mut directives = Vec::new();
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut start = 1;
+    let mut line = 1;
+    let mut depth = 0usize;
+    let mut chars = config.chars();
+
+    while let Some(c) = chars.next() {
+        if word.is_none() && words.is_empty() {
+            start = line;
+        }
+        match c {
+            '\n' | ' ' | '\t' | '\r' | ';' | '{' | '}' => {
+                words.extend(word.take());
+                match c {
+                    ';' if words.is_empty() => return Err(unexpected(line, "\";\"")),
+                    ';' => directives.push((start, std::mem::take(&mut words))),
+                    '{' if words.is_empty() => return Err(unexpected(line, "\"{\"")),
+                    '{' => {
+                        words.clear();
+                        depth += 1;
+                    }
+                    '}' if !words.is_empty() || depth == 0 => {
+                        return Err(unexpected(line, "\"}\""));
+                    }
+                    '}' => depth -= 1,
+                    '\n' => line += 1,
+                    _ => {}
+                }
+            }
+            '#' if word.is_none() => {
+                chars.by_ref().find(|&c| c == '\n');
+                line += 1;
+            }
+            '"' | '\'' if word.is_none() => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') => quoted.extend(chars.next()),
+                        Some(other) => {
+                            line += usize::from(other == '\n');
+                            quoted.push(other);
+                        }
+                        None => return Err(unexpected(line, "end of file in a quoted string")),
+                    }
+                }
+                word = Some(quoted);
+            }
+            _ => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if word.is_some() || !words.is_empty() {
+        return Err(unexpected(line, "end of file, expecting \";\""));
+    }
+    if depth > 0 {
+        return Err(unexpected(line, "end of file, expecting \"}\""));
+    }
+    Ok(directives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const PEER: &str = "10.0.0.2";
+
+    fn extract(config: &str, forwarded_for: &str, peer: &str) -> String {
+        let config = RealIpConfig::parse(config).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("x-forwarded-for".to_string(), forwarded_for.to_string());
+        IpExtractor::nginx_realip(&config)
+            .extract(&headers, Some(peer.to_string()))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_realip_semantics() {
+        let recursive = r#"
+            http {
+                # Load balancers
+                set_real_ip_from 10.0.0.0/8;
+                set_real_ip_from 192.0.2.1;
+                server {
+                    listen 80;
+                    real_ip_header "X-Forwarded-For";
+                    real_ip_recursive on;
+                }
+            }
+        "#;
+        let single = "set_real_ip_from 10.0.0.0/8; real_ip_header X-Forwarded-For;";
+
+        let chain = "203.0.113.9, 198.51.100.7,10.1.1.1 192.0.2.1";
+        assert_eq!(extract(recursive, chain, PEER), "198.51.100.7");
+        assert_eq!(extract(single, chain, PEER), "192.0.2.1");
+        assert_eq!(extract(recursive, chain, "203.0.113.1"), "203.0.113.1");
+        // Every address trusted: the leftmost one
+        assert_eq!(extract(recursive, "10.1.1.1, 192.0.2.1", PEER), "10.1.1.1");
+        // A bad token keeps the last address found
+        assert_eq!(extract(recursive, "unknown, 10.1.1.1", PEER), "10.1.1.1");
+        assert_eq!(extract(recursive, "10.1.1.1, unknown", PEER), PEER);
+    }
+
+    #[test]
+    fn test_repeated_header() {
+        let headers = vec![
+            ("X-Real-IP".to_string(), "198.51.100.7".to_string()),
+            ("x-forwarded-for".to_string(), "203.0.113.9".to_string()),
+            ("x-real-ip".to_string(), "192.0.2.1".to_string()),
+            ("x-forwarded-for".to_string(), "198.51.100.2".to_string()),
+        ];
+        let extract = |config: &str| {
+            let config = RealIpConfig::parse(config).unwrap();
+            IpExtractor::nginx_realip(&config)
+                .extract(&headers, Some(PEER.to_string()))
+                .unwrap()
+                .to_string()
+        };
+
+        // A second X-Real-IP line from the client is ignored
+        assert_eq!(
+            extract("set_real_ip_from 10.0.0.0/8; real_ip_header X-Real-IP;"),
+            "198.51.100.7"
+        );
+        // X-Forwarded-For lines are combined
+        assert_eq!(
+            extract("set_real_ip_from 10.0.0.0/8; real_ip_header X-Forwarded-For;"),
+            "198.51.100.2"
+        );
+    }
+
+    #[test]
+    fn test_proxy_protocol_peers() {
+        let config =
+            RealIpConfig::parse("set_real_ip_from 10.0.0.0/8; real_ip_header proxy_protocol;")
+                .unwrap();
+        let source = Some("198.51.100.7".parse().unwrap());
+        let client = |peer: &str, source| {
+            config
+                .proxy_protocol_client(peer.parse().unwrap(), source)
+                .to_string()
+        };
+
+        assert_eq!(client("10.0.0.2", source), "198.51.100.7");
+        // Like the header, the PROXY source is ignored from untrusted peers
+        assert_eq!(client("203.0.113.9", source), "203.0.113.9");
+        assert_eq!(client("10.0.0.2", None), "10.0.0.2");
+
+        let header = RealIpConfig::parse("set_real_ip_from 10.0.0.0/8;").unwrap();
+        assert_eq!(
+            header
+                .proxy_protocol_client("10.0.0.2".parse().unwrap(), source)
+                .to_string(),
+            "10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let config =
+            RealIpConfig::parse("set_real_ip_from unix:; real_ip_header proxy_protocol;").unwrap();
+        assert!(config.unix);
+        assert_eq!(config.real_ip_header, RealIpHeader::ProxyProtocol);
+        assert!(config.rules().is_empty());
+        assert_eq!(RealIpConfig::parse("").unwrap(), RealIpConfig::default());
+
+        for invalid in [
+            "set_real_ip_from lb.example.com;",
+            "real_ip_recursive maybe;",
+            "real_ip_header;",
+            "set_real_ip_from 10.0.0.0/8",
+            "server { listen 80; ",
+        ] {
+            assert!(RealIpConfig::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+/* src/compiled.rs */
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::addr::ClientAddr;
+use crate::error::{ConfigError, Result};
+use crate::extraction::Extraction;
+use crate::extractor::IpExtractor;
+use crate::headers::HeaderSource;
+use crate::rule::{ChainSelection, Condition, HeaderRule};
+use crate::strategy::Strategy;
+
+/// A validated [`IpExtractor`], built once at startup and shared by reference
+/// counting.
+///
+/// Header names are lowercased, so maps keyed by lowercase names find them
+/// without scanning, and every range set is indexed. With the `http` feature,
+/// the header name of each rule is also interned, so an `http::HeaderMap` is
+/// searched without parsing it on every request. Cloning is cheap, so one
+/// extractor can be handed to every worker or service instance.
+///
+/// ```rust
+/// use real::{CidrSet, IpExtractor};
+/// use std::collections::HashMap;
+///
+/// let extractor = IpExtractor::new()
+///     .with_headers(vec!["X-Forwarded-For".to_string()])
+///     .trusted_proxies("10.0.0.0/8".parse::<CidrSet>().unwrap())
+///     .build()
+///     .unwrap();
+///
+/// let mut headers = HashMap::new();
+/// headers.insert("x-forwarded-for".to_string(), "198.51.100.7, 10.0.0.3".to_string());
+///
+/// let worker = extractor.clone();
+/// assert_eq!(worker.extract(&headers, Some("10.0.0.2".to_string())), Some("198.51.100.7".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompiledExtractor {
+    config: Arc<IpExtractor>,
+}
+
+impl IpExtractor {
+    /// Validate the configuration and compile it for sharing.
+    ///
+    /// Fails when a header name, including those in selections and conditions,
+    /// is not a valid HTTP field name, or when `max_chain_length` is zero.
+    pub fn build(self) -> std::result::Result<CompiledExtractor, ConfigError> {
+        if self.max_chain_length == Some(0) {
+            return Err(ConfigError::ZeroChainLength);
+        }
+        for rule in self.rules.iter().chain(
+            self.peer_rules
+                .iter()
+                .flat_map(|peer_rule| &peer_rule.rules),
+        ) {
+            validate_rule(rule)?;
+        }
+        Ok(self.compile())
+    }
+
+    /// Normalize and index the configuration without validating it.
+    pub(crate) fn compile(mut self) -> CompiledExtractor {
+        // The field may have been set without the builder
+        if let Some(hops) = self.trusted_hops {
+            self = self.trusted_hops(hops);
+        }
+        if let Some(trusted) = &mut self.trusted_proxies {
+            trusted.compile();
+        }
+        for rule in &mut self.rules {
+            compile_rule(rule);
+        }
+        for peer_rule in &mut self.peer_rules {
+            peer_rule.peers.compile();
+            for rule in &mut peer_rule.rules {
+                compile_rule(rule);
+            }
+        }
+        CompiledExtractor {
+            config: Arc::new(self),
+        }
+    }
+}
+
+impl CompiledExtractor {
+    /// The compiled configuration.
+    pub fn config(&self) -> &IpExtractor {
+        &self.config
+    }
+
+    /// See [`IpExtractor::extract`].
+    pub fn extract<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<IpAddr> {
+        self.config.extract(headers, fallback_ip)
+    }
+
+    /// See [`IpExtractor::extract_addr`].
+    pub fn extract_addr<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<ClientAddr> {
+        self.config.extract_addr(headers, fallback_ip)
+    }
+
+    /// See [`IpExtractor::extract_detailed`].
+    pub fn extract_detailed<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<Extraction> {
+        self.config.extract_detailed(headers, fallback_ip)
+    }
+
+    /// See [`IpExtractor::try_extract`].
+    pub fn try_extract<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Result<Extraction> {
+        self.config.try_extract(headers, fallback_ip)
+    }
+}
+
+impl Strategy for CompiledExtractor {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        self.config.client_addr(headers, peer)
+    }
+}
+
+/// Check every header name a rule reads.
+fn validate_rule(rule: &HeaderRule) -> std::result::Result<(), ConfigError> {
+    validate_name(&rule.name)?;
+    if let ChainSelection::HopsFromHeader(header) = &rule.selection {
+        validate_name(header)?;
+    }
+    for condition in &rule.conditions {
+        match condition {
+            Condition::LastHopIn { header, .. } | Condition::HeaderEquals { header, .. } => {
+                validate_name(header)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A field name is a non-empty RFC 9110 token.
+fn validate_name(name: &str) -> std::result::Result<(), ConfigError> {
+    let token = |byte: u8| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte);
+    if name.is_empty() || !name.bytes().all(token) {
+        return Err(ConfigError::InvalidHeaderName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Lowercase and intern the header names of a rule and index its range sets.
+fn compile_rule(rule: &mut HeaderRule) {
+    rule.name.make_ascii_lowercase();
+    #[cfg(feature = "http")]
+    {
+        rule.header_name = http::HeaderName::from_bytes(rule.name.as_bytes()).ok();
+    }
+    if let Some(peers) = &mut rule.trusted_peers {
+        peers.compile();
+    }
+    match &mut rule.selection {
+        ChainSelection::HopsFromHeader(header) => header.make_ascii_lowercase(),
+        ChainSelection::RightmostUntrusted(trusted) => trusted.compile(),
+        ChainSelection::Inherit
+        | ChainSelection::Leftmost
+        | ChainSelection::Rightmost
+        | ChainSelection::TrustedHops(_)
+        | ChainSelection::Custom(_) => {}
+    }
+    for condition in &mut rule.conditions {
+        match condition {
+            Condition::LastHopIn { header, ranges } => {
+                header.make_ascii_lowercase();
+                ranges.compile();
+            }
+            Condition::HeaderEquals { header, .. } => header.make_ascii_lowercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::aws;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_build_normalizes_and_indexes() {
+        let extractor = IpExtractor::new()
+            .with_rules(vec![
+                HeaderRule::comma_list("X-Forwarded-For")
+                    .trusted_peers(aws::vpc_ranges())
+                    .selection(ChainSelection::RightmostUntrusted(aws::vpc_ranges())),
+            ])
+            .build()
+            .unwrap();
+        let rule = &extractor.config().rules[0];
+        assert_eq!(rule.name, "x-forwarded-for");
+        assert!(rule.trusted_peers.as_ref().unwrap().is_compiled());
+
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 10.0.3.3".to_string(),
+        );
+        let extraction = extractor
+            .extract_detailed(&headers, Some("10.0.1.20".to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            extraction.source,
+            crate::extraction::Source::Header("x-forwarded-for".to_string())
+        );
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_build_interns_names() {
+        let extractor = IpExtractor::new()
+            .with_headers(vec!["X-Real-IP".to_string()])
+            .build()
+            .unwrap();
+        let rule = &extractor.config().rules[0];
+        assert_eq!(rule.header_name.as_ref().unwrap(), "x-real-ip");
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-real-ip", "198.51.100.7".parse().unwrap());
+        assert_eq!(
+            extractor.extract(&headers, Some("10.0.0.2".to_string())),
+            Some("198.51.100.7".parse().unwrap())
+        );
+
+        // A name changed after building is looked up as spelled
+        let mut config = extractor.config().clone();
+        config.rules[0].name = "x-client-ip".to_string();
+        headers.insert("x-client-ip", "203.0.113.9".parse().unwrap());
+        assert_eq!(
+            config.extract(&headers, Some("10.0.0.2".to_string())),
+            Some("203.0.113.9".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_build_rejects_bad_config() {
+        let invalid = IpExtractor::new().with_headers(vec!["X-Real IP".to_string()]);
+        assert_eq!(
+            invalid.build().unwrap_err(),
+            ConfigError::InvalidHeaderName("X-Real IP".to_string())
+        );
+
+        let condition = HeaderRule::single_ip("x-azure-socketip").when(Condition::HeaderEquals {
+            header: String::new(),
+            value: "fdid".to_string(),
+        });
+        assert!(
+            IpExtractor::new()
+                .with_rules(vec![condition])
+                .build()
+                .is_err()
+        );
+
+        assert_eq!(
+            IpExtractor::new().max_chain_length(0).build().unwrap_err(),
+            ConfigError::ZeroChainLength
+        );
+    }
+}
@@ -1,7 +1,11 @@
 /* src/error.rs */
 
+use std::net::IpAddr;
+
 use thiserror::Error;
 
+use crate::extraction::Anomaly;
+
 /// Result type alias for operations that may fail with `RealIpError`.
 pub type Result<T> = std::result::Result<T, RealIpError>;
 
@@ -12,7 +16,96 @@ pub enum RealIpError {
     #[error("Invalid IP address format: {0}")]
     InvalidIpFormat(String),
 
-    /// No valid IP address found in headers or fallback.
+    /// Candidates were found in headers, but none passed validation and there is no fallback.
     #[error("No valid IP address found")]
     NoValidIp,
+
+    /// No forwarding header matched and no fallback address was given.
+    #[error("No source for the client address")]
+    NoSource,
+
+    /// A header value contained a token that is not an address.
+    #[error("Malformed {header} header: {token:?}")]
+    MalformedHeader {
+        /// Header name.
+        header: String,
+        /// The offending token.
+        token: String,
+    },
+
+    /// Forwarding headers were sent by a peer that is not a trusted proxy.
+    #[error("Forwarding headers from untrusted peer {}", display_peer(.0))]
+    UntrustedPeer(Option<IpAddr>),
+
+    /// A header chain has more entries than allowed.
+    #[error("{header} chain has {len} entries, more than the maximum of {max}")]
+    ChainTooLong {
+        /// Header name.
+        header: String,
+        /// Number of entries in the chain.
+        len: usize,
+        /// Configured maximum.
+        max: usize,
+    },
+
+    /// A header chain has fewer entries than the configured trusted hops.
+    #[error("{header} chain has {len} entries, fewer than the {hops} trusted hops")]
+    ChainTooShort {
+        /// Header name.
+        header: String,
+        /// Number of entries in the chain.
+        len: usize,
+        /// Configured trusted hops.
+        hops: usize,
+    },
+
+    /// Headers disagree, and the conflict policy does not allow it.
+    #[error("Conflicting headers: {0}")]
+    ConflictingHeaders(Anomaly),
+
+    /// Invalid CIDR range format.
+    #[error("Invalid CIDR range: {0}")]
+    InvalidCidr(String),
+
+    /// Invalid RFC 7239 `Forwarded` header value.
+    #[error("Invalid Forwarded header: {0}")]
+    InvalidForwarded(String),
+
+    /// Invalid PROXY protocol header.
+    #[error("Invalid PROXY protocol header: {0}")]
+    InvalidProxyHeader(String),
+
+    /// Invalid provider IP range list.
+    #[error("Invalid IP range list: {0}")]
+    InvalidRangeList(String),
+
+    /// Invalid proxy server configuration.
+    #[error("Invalid proxy configuration: {0}")]
+    InvalidConfig(String),
+
+    /// The request would be rejected by the proxy being reproduced.
+    #[error("Rejected with status {status}: no valid address in {header}")]
+    Rejected {
+        /// Header that should have held the address.
+        header: String,
+        /// Status code the proxy responds with.
+        status: String,
+    },
+}
+
+/// Errors found by [`IpExtractor::build`](crate::IpExtractor::build) in an
+/// extractor configuration.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A configured header name is not a valid HTTP field name.
+    #[error("Invalid header name: {0:?}")]
+    InvalidHeaderName(String),
+
+    /// `max_chain_length` is zero, so every header would be ignored.
+    #[error("max_chain_length of 0 ignores every header")]
+    ZeroChainLength,
+}
+
+fn display_peer(peer: &Option<IpAddr>) -> String {
+    peer.map_or_else(|| "(unknown)".to_string(), |peer| peer.to_string())
 }
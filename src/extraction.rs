@@ -0,0 +1,161 @@
+/* src/extraction.rs */
+
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::addr::ClientAddr;
+use crate::rule::ChainEntry;
+
+/// Where an extracted address came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A forwarding header, by the name of its rule.
+    Header(String),
+    /// The fallback address, usually the socket peer.
+    Fallback,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(name) => f.write_str(name),
+            Self::Fallback => f.write_str("fallback"),
+        }
+    }
+}
+
+/// Why a candidate address was not used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// A private or unique local address, while private IPs are not trusted.
+    Private,
+    /// A loopback address, while private IPs are not trusted.
+    Loopback,
+    /// A link-local address, while private IPs are not trusted.
+    LinkLocal,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Private => f.write_str("private address"),
+            Self::Loopback => f.write_str("loopback address"),
+            Self::LinkLocal => f.write_str("link-local address"),
+        }
+    }
+}
+
+/// A candidate address that was found but not used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    /// The candidate address.
+    pub addr: ClientAddr,
+    /// Where the candidate came from.
+    pub source: Source,
+    /// Position of the candidate in its chain, counted from the left.
+    pub index: Option<usize>,
+    /// Why the candidate was not used.
+    pub reason: RejectReason,
+}
+
+/// A sign that forwarding headers may have been spoofed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// Two headers named different client addresses.
+    HeaderDisagreement {
+        /// The header with higher priority.
+        first: String,
+        /// The address it named.
+        first_ip: IpAddr,
+        /// The header with lower priority.
+        second: String,
+        /// The address it named.
+        second_ip: IpAddr,
+    },
+    /// A single-address header, such as `X-Real-IP`, names an address that is
+    /// missing from a forwarding chain.
+    NotInChain {
+        /// The single-address header.
+        header: String,
+        /// The address it named.
+        ip: IpAddr,
+        /// The chain header that does not contain it.
+        chain_header: String,
+    },
+    /// A header that should appear once was sent several times.
+    DuplicateHeader {
+        /// Header name.
+        header: String,
+        /// Number of instances.
+        count: usize,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HeaderDisagreement {
+                first,
+                first_ip,
+                second,
+                second_ip,
+            } => write!(
+                f,
+                "{} says {}, {} says {}",
+                first, first_ip, second, second_ip
+            ),
+            Self::NotInChain {
+                header,
+                ip,
+                chain_header,
+            } => write!(f, "{} {} is not in the {} chain", header, ip, chain_header),
+            Self::DuplicateHeader { header, count } => {
+                write!(f, "{} sent {} times", header, count)
+            }
+        }
+    }
+}
+
+/// The result of an extraction, with enough provenance to debug it.
+///
+/// ```rust
+/// use real::{IpExtractor, Source};
+/// use std::collections::HashMap;
+///
+/// let mut headers = HashMap::new();
+/// headers.insert("x-forwarded-for".to_string(), "203.0.113.9, 10.0.0.2".to_string());
+///
+/// let extraction = IpExtractor::new()
+///     .use_first_forwarded(false)
+///     .extract_detailed(&headers, Some("10.0.0.1".to_string()))
+///     .unwrap();
+/// assert_eq!(extraction.source, Source::Fallback);
+/// assert_eq!(extraction.rejected[0].index, Some(1));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extraction {
+    /// The chosen client IP.
+    pub ip: IpAddr,
+    /// The client port, if the source carried one.
+    pub port: Option<u16>,
+    /// Where the IP came from.
+    pub source: Source,
+    /// Position of the IP in the chain, counted from the left. `None` for the fallback.
+    pub index: Option<usize>,
+    /// The full parsed chain of the source header. Empty for the fallback.
+    pub chain: Vec<ChainEntry>,
+    /// Candidates that were found but not used, in the order they were considered.
+    pub rejected: Vec<Rejected>,
+    /// Signs of spoofing found while cross-checking the headers.
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl Extraction {
+    /// The chosen address with its port.
+    pub fn addr(&self) -> ClientAddr {
+        ClientAddr {
+            ip: self.ip,
+            port: self.port,
+        }
+    }
+}
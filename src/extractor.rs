@@ -1,35 +1,85 @@
 /* src/extractor.rs */
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net::IpAddr;
 
-/// Type alias for header maps. Can be any map-like structure with string keys and values.
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::error::{RealIpError, Result};
+use crate::extraction::{Anomaly, Extraction, RejectReason, Rejected, Source};
+use crate::headers::HeaderSource;
+use crate::rule::{ChainEntry, ChainSelection, HeaderFormat, HeaderRule, PeerRule};
+
+/// Type alias for header maps.
+///
+/// It cannot hold repeated headers; any [`HeaderSource`], such as
+/// `Vec<(String, String)>` or `http::HeaderMap`, can be passed instead.
 pub type HeaderMap = HashMap<String, String>;
 
+/// Header that proxies append the address of their peer to.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// What to do when headers disagree about the client address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Use the first header in priority order and report anomalies.
+    #[default]
+    FirstWins,
+    /// Fail when headers name different addresses. Other anomalies are only reported.
+    RequireConsensus,
+    /// Fail on any anomaly.
+    Reject,
+}
+
 /// Configuration for IP extraction behavior.
+///
+/// For new code, the [`Strategy`](crate::Strategy) types state the trust
+/// model more directly than the `trust_private_ips`, `use_first_forwarded` and
+/// `with_headers` knobs.
 #[derive(Debug, Clone)]
 pub struct IpExtractor {
-    /// Headers to check for real IP, in order of preference.
-    pub headers: Vec<String>,
+    /// Header rules to check for real IP, in order of preference.
+    pub rules: Vec<HeaderRule>,
+    /// Header rules chosen by the socket peer. When set, they replace `rules`.
+    pub peer_rules: Vec<PeerRule>,
     /// Whether to trust private IP addresses from headers.
     pub trust_private_ips: bool,
     /// Whether to use the first IP in X-Forwarded-For chain.
     pub use_first_forwarded: bool,
+    /// Proxies allowed to set forwarding headers. When set, chains are walked
+    /// from the right and `use_first_forwarded` is ignored.
+    pub trusted_proxies: Option<CidrSet>,
+    /// Number of trusted proxies that append to X-Forwarded-For. When set, only
+    /// that header is read and the other chain options are ignored.
+    pub trusted_hops: Option<usize>,
+    /// Maximum number of entries accepted in a header chain.
+    pub max_chain_length: Option<usize>,
+    /// What to do when headers disagree.
+    pub conflict_policy: ConflictPolicy,
+    /// The X-Forwarded-For rule of `trusted_hops`, built once.
+    hops_rule: Option<HeaderRule>,
 }
 
 impl Default for IpExtractor {
     fn default() -> Self {
         Self {
-            headers: vec![
-                "x-real-ip".to_string(),
-                "cf-connecting-ip".to_string(),
-                "x-forwarded-for".to_string(),
-                "x-forwarded".to_string(),
-                "forwarded-for".to_string(),
-                "forwarded".to_string(),
+            rules: vec![
+                HeaderRule::single_ip("x-real-ip"),
+                HeaderRule::single_ip("cf-connecting-ip"),
+                HeaderRule::comma_list("x-forwarded-for"),
+                HeaderRule::comma_list("x-forwarded"),
+                HeaderRule::comma_list("forwarded-for"),
+                HeaderRule::forwarded("forwarded"),
             ],
+            peer_rules: Vec::new(),
             trust_private_ips: false,
             use_first_forwarded: true,
+            trusted_proxies: None,
+            trusted_hops: None,
+            max_chain_length: None,
+            conflict_policy: ConflictPolicy::FirstWins,
+            hops_rule: None,
         }
     }
 }
@@ -41,8 +91,50 @@ impl IpExtractor {
     }
 
     /// Set headers to check for real IP.
+    ///
+    /// Each header gets the format of a well-known header of that name, see
+    /// [`HeaderRule::infer`]. Use [`with_rules`](Self::with_rules) for full control.
     pub fn with_headers(mut self, headers: Vec<String>) -> Self {
-        self.headers = headers;
+        self.rules = headers.into_iter().map(HeaderRule::infer).collect();
+        self
+    }
+
+    /// Set header rules to check for real IP, in order of preference.
+    pub fn with_rules(mut self, rules: Vec<HeaderRule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Honor `rules` for requests from peers inside `peers`.
+    ///
+    /// Peer rules are checked in the order they were added, and the first
+    /// match replaces [`with_rules`](Self::with_rules) for the request. Peers
+    /// matching none of them get the socket address.
+    ///
+    /// ```rust
+    /// use real::{ChainSelection, CidrSet, HeaderRule, IpExtractor};
+    /// use real::presets::cloudflare;
+    /// use std::collections::HashMap;
+    ///
+    /// let internal: CidrSet = "10.0.0.0/8".parse().unwrap();
+    /// let extractor = IpExtractor::new()
+    ///     .peer_rules(cloudflare::ranges(), vec![HeaderRule::single_ip("cf-connecting-ip")])
+    ///     .peer_rules(
+    ///         internal,
+    ///         vec![HeaderRule::comma_list("x-forwarded-for").selection(ChainSelection::TrustedHops(1))],
+    ///     );
+    ///
+    /// let mut headers = HashMap::new();
+    /// headers.insert("cf-connecting-ip".to_string(), "198.51.100.7".to_string());
+    /// headers.insert("x-forwarded-for".to_string(), "192.0.2.1".to_string());
+    ///
+    /// let ip = |peer: &str| extractor.extract(&headers, Some(peer.to_string())).unwrap().to_string();
+    /// assert_eq!(ip("173.245.48.1"), "198.51.100.7");
+    /// assert_eq!(ip("10.0.0.2"), "192.0.2.1");
+    /// assert_eq!(ip("203.0.113.9"), "203.0.113.9");
+    /// ```
+    pub fn peer_rules(mut self, peers: impl Into<CidrSet>, rules: Vec<HeaderRule>) -> Self {
+        self.peer_rules.push(PeerRule::new(peers, rules));
         self
     }
 
@@ -58,82 +150,545 @@ impl IpExtractor {
         self
     }
 
+    /// Only honor forwarding headers from peers inside these ranges.
+    ///
+    /// Chains are then walked from the right, skipping trusted entries, and the
+    /// first untrusted address is returned, like nginx's `real_ip_recursive on`.
+    /// If every entry is trusted, the leftmost one is used.
+    pub fn trusted_proxies(mut self, proxies: impl Into<CidrSet>) -> Self {
+        self.trusted_proxies = Some(proxies.into());
+        self
+    }
+
+    /// Take the client from a fixed number of proxy hops, like Envoy's `xff_num_trusted_hops`.
+    ///
+    /// Each of the `hops` proxies in front of the service appends the address it
+    /// saw to X-Forwarded-For, so the client is the `hops`-th entry from the right.
+    /// With `0`, headers are ignored and the socket peer is used. A chain shorter
+    /// than `hops` means the request did not pass through every proxy, and
+    /// extraction fails instead of falling back. As in Envoy, the entry is
+    /// returned even when it is private, whatever `trust_private_ips` says.
+    pub fn trusted_hops(mut self, hops: usize) -> Self {
+        self.trusted_hops = Some(hops);
+        self.hops_rule = Some(Self::forwarded_for_hops(hops));
+        self
+    }
+
+    /// Ignore header chains with more than `max` entries, or reject them with
+    /// [`try_extract`](Self::try_extract).
+    pub fn max_chain_length(mut self, max: usize) -> Self {
+        self.max_chain_length = Some(max);
+        self
+    }
+
+    /// Set what to do when headers disagree about the client address.
+    ///
+    /// Anomalies are always reported in [`Extraction::anomalies`]; with a
+    /// stricter policy they also fail the extraction.
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
     /// Extract the real IP address from headers with fallback.
-    pub fn extract(&self, headers: &HeaderMap, fallback_ip: Option<String>) -> Option<IpAddr> {
+    ///
+    /// With `trusted_proxies` configured, `fallback_ip` is the socket peer and
+    /// headers are ignored entirely unless it is a trusted proxy.
+    pub fn extract<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<IpAddr> {
+        self.extract_detailed(headers, fallback_ip)
+            .map(|extraction| extraction.ip)
+    }
+
+    /// Extract the real client address, keeping the port when the source carried one.
+    ///
+    /// Header entries and `fallback_ip` may be bare addresses or use the
+    /// `ip:port` and `[ipv6]:port` forms.
+    pub fn extract_addr<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<ClientAddr> {
+        self.extract_detailed(headers, fallback_ip)
+            .map(|extraction| extraction.addr())
+    }
+
+    /// Extract the real client address along with where it came from, the
+    /// parsed chain and any candidates that were rejected.
+    pub fn extract_detailed<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Option<Extraction> {
+        self.run(headers, fallback_ip, false).ok()
+    }
+
+    /// Extract the real client address, failing on anything suspicious.
+    ///
+    /// Unlike [`extract_detailed`](Self::extract_detailed), which skips what it
+    /// cannot use, this returns an error for malformed header values or fallback
+    /// addresses, forwarding headers from an untrusted peer, and chains longer than
+    /// `max_chain_length`, so strict deployments can reject the request.
+    pub fn try_extract<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+    ) -> Result<Extraction> {
+        self.run(headers, fallback_ip, true)
+    }
+
+    /// Shared implementation of the extraction APIs. In lenient mode, problems
+    /// that only affect one source are skipped instead of returned.
+    fn run<H: HeaderSource + ?Sized>(
+        &self,
+        headers: &H,
+        fallback_ip: Option<String>,
+        strict: bool,
+    ) -> Result<Extraction> {
+        let fallback = match fallback_ip.map(|fallback| fallback.parse::<ClientAddr>()) {
+            Some(Ok(addr)) => Some(addr),
+            Some(Err(err)) if strict => return Err(err),
+            Some(Err(_)) | None => None,
+        };
+        let peer = fallback.map(|peer| peer.ip);
+        let mut rejected = Vec::new();
+
+        // Forwarding headers are only meaningful when a trusted proxy set them
+        if let Some(trusted) = &self.trusted_proxies
+            && !peer.is_some_and(|peer| trusted.contains(&peer))
+        {
+            if strict && self.sends_forwarding_headers(headers) {
+                return Err(RealIpError::UntrustedPeer(peer));
+            }
+            return Self::finish(fallback, rejected);
+        }
+
+        if let Some(hops) = self.trusted_hops {
+            if hops == 0 {
+                return Self::finish(fallback, rejected);
+            }
+            // Only the X-Forwarded-For chain counts hops
+            let rule = self.hops_rule(hops);
+            if let Some(extraction) =
+                self.apply_rule(&rule, headers, peer, strict, &mut rejected)?
+            {
+                return Ok(extraction);
+            }
+            // The rule always applies, so the chain was missing or over the length limit
+            let len =
+                Self::header_value(headers, &rule).map_or(0, |value| rule.parse(&value).len());
+            return Err(match self.max_chain_length {
+                Some(max) if len > max => RealIpError::ChainTooLong {
+                    header: rule.name.clone(),
+                    len,
+                    max,
+                },
+                _ => RealIpError::ChainTooShort {
+                    header: rule.name.clone(),
+                    len,
+                    hops,
+                },
+            });
+        }
+
+        let rules = if self.peer_rules.is_empty() {
+            &self.rules
+        } else {
+            match self.peer_rules.iter().find(|rule| rule.matches(peer)) {
+                Some(peer_rule) => &peer_rule.rules,
+                None => {
+                    if strict
+                        && self
+                            .peer_rules
+                            .iter()
+                            .flat_map(|peer_rule| &peer_rule.rules)
+                            .any(|rule| Self::header_value(headers, rule).is_some())
+                    {
+                        return Err(RealIpError::UntrustedPeer(peer));
+                    }
+                    return Self::finish(fallback, rejected);
+                }
+            }
+        };
+
         // Try to extract from headers first
-        if let Some(ip) = self.extract_from_headers(headers) {
-            return Some(ip);
+        let mut found = None;
+        for rule in rules {
+            if let Some(extraction) = self.apply_rule(rule, headers, peer, strict, &mut rejected)? {
+                found = Some(extraction);
+                break;
+            }
         }
 
         // Fallback to provided IP
-        if let Some(fallback) = fallback_ip {
-            if let Ok(ip) = fallback.parse::<IpAddr>() {
-                return Some(ip);
-            }
+        let extraction = match found {
+            Some(extraction) => extraction,
+            None => Self::finish(fallback, rejected)?,
+        };
+        self.check_consistency(extraction, rules, headers, peer)
+    }
+
+    /// Whether the request carries any header the configuration could read,
+    /// from `rules`, `peer_rules` or `trusted_hops`.
+    fn sends_forwarding_headers<H: HeaderSource + ?Sized>(&self, headers: &H) -> bool {
+        (self.trusted_hops.is_some_and(|hops| hops > 0)
+            && HeaderSource::get(headers, FORWARDED_FOR).is_some())
+            || self
+                .rules
+                .iter()
+                .chain(
+                    self.peer_rules
+                        .iter()
+                        .flat_map(|peer_rule| &peer_rule.rules),
+                )
+                .any(|rule| Self::header_value(headers, rule).is_some())
+    }
+
+    /// The rule of `trusted_hops`, built on the fly when the field was set directly.
+    fn hops_rule(&self, hops: usize) -> Cow<'_, HeaderRule> {
+        if let Some(rule) = &self.hops_rule
+            && matches!(rule.selection, ChainSelection::TrustedHops(built) if built == hops)
+        {
+            return Cow::Borrowed(rule);
         }
+        Cow::Owned(Self::forwarded_for_hops(hops))
+    }
 
-        None
+    /// A rule taking the `hops`-th X-Forwarded-For entry from the right.
+    fn forwarded_for_hops(hops: usize) -> HeaderRule {
+        let rule =
+            HeaderRule::comma_list(FORWARDED_FOR).selection(ChainSelection::TrustedHops(hops));
+        #[cfg(feature = "http")]
+        let rule = HeaderRule {
+            header_name: Some(http::HeaderName::from_static(FORWARDED_FOR)),
+            ..rule
+        };
+        rule
     }
 
-    /// Extract IP from headers only.
-    fn extract_from_headers(&self, headers: &HeaderMap) -> Option<IpAddr> {
-        for header_name in &self.headers {
-            if let Some(header_value) = headers.get(&header_name.to_lowercase()) {
-                if let Some(ip) = self.parse_header_value(header_value) {
-                    if self.is_valid_ip(&ip) {
-                        return Some(ip);
-                    }
+    /// Cross-check all present headers, record anomalies and apply the conflict policy.
+    fn check_consistency<H: HeaderSource + ?Sized>(
+        &self,
+        mut extraction: Extraction,
+        rules: &[HeaderRule],
+        headers: &H,
+        peer: Option<IpAddr>,
+    ) -> Result<Extraction> {
+        let mut candidates = Vec::new();
+        let mut chains = Vec::new();
+
+        for rule in rules {
+            if !rule.applies(peer, headers) {
+                continue;
+            }
+            let Some(value) = Self::header_value(headers, rule) else {
+                continue;
+            };
+
+            let single = matches!(rule.format, HeaderFormat::SingleIp | HeaderFormat::IpPort);
+            // Repeated header lines are combined with commas
+            if single && value.contains(',') {
+                extraction.anomalies.push(Anomaly::DuplicateHeader {
+                    header: rule.name.clone(),
+                    count: value.split(',').count(),
+                });
+            }
+
+            let chain = rule.parse(&value);
+            if let Ok(Some((_, addr))) = self.select(rule, &chain, peer, headers) {
+                candidates.push((rule, single, addr.ip));
+            }
+            if matches!(
+                rule.format,
+                HeaderFormat::CommaList | HeaderFormat::Forwarded
+            ) {
+                chains.push((rule, chain));
+            }
+        }
+
+        if let Some(((first, _, first_ip), rest)) = candidates.split_first() {
+            for (second, _, second_ip) in rest {
+                if second_ip != first_ip {
+                    extraction.anomalies.push(Anomaly::HeaderDisagreement {
+                        first: first.name.clone(),
+                        first_ip: *first_ip,
+                        second: second.name.clone(),
+                        second_ip: *second_ip,
+                    });
                 }
             }
         }
-        None
+
+        for (rule, _, ip) in candidates.iter().filter(|(_, single, _)| *single) {
+            for (chain_rule, chain) in &chains {
+                if !chain
+                    .iter()
+                    .any(|entry| entry.addr().is_some_and(|addr| addr.ip == *ip))
+                {
+                    extraction.anomalies.push(Anomaly::NotInChain {
+                        header: rule.name.clone(),
+                        ip: *ip,
+                        chain_header: chain_rule.name.clone(),
+                    });
+                }
+            }
+        }
+
+        let conflict = match self.conflict_policy {
+            ConflictPolicy::FirstWins => None,
+            ConflictPolicy::RequireConsensus => extraction
+                .anomalies
+                .iter()
+                .find(|anomaly| matches!(anomaly, Anomaly::HeaderDisagreement { .. })),
+            ConflictPolicy::Reject => extraction.anomalies.first(),
+        };
+        match conflict {
+            Some(anomaly) => Err(RealIpError::ConflictingHeaders(anomaly.clone())),
+            None => Ok(extraction),
+        }
     }
 
-    /// Parse header value and extract IP address.
-    fn parse_header_value(&self, value: &str) -> Option<IpAddr> {
-        let value = value.trim();
+    /// Read and parse the header of `rule`, then select the client from its chain.
+    ///
+    /// Returns `Ok(None)` when the rule does not apply and the next one should be tried.
+    fn apply_rule<H: HeaderSource + ?Sized>(
+        &self,
+        rule: &HeaderRule,
+        headers: &H,
+        peer: Option<IpAddr>,
+        strict: bool,
+        rejected: &mut Vec<Rejected>,
+    ) -> Result<Option<Extraction>> {
+        if !rule.applies(peer, headers) {
+            return Ok(None);
+        }
+        let Some(value) = Self::header_value(headers, rule) else {
+            return Ok(None);
+        };
 
-        // Handle X-Forwarded-For format: "client, proxy1, proxy2"
-        if value.contains(',') {
-            let ips: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
-            let ip_iter: Box<dyn Iterator<Item = &&str>> = if self.use_first_forwarded {
-                Box::new(ips.iter())
+        let chain = rule.parse(&value);
+        if let Some(max) = self.max_chain_length
+            && chain.len() > max
+        {
+            if strict {
+                return Err(RealIpError::ChainTooLong {
+                    header: rule.name.clone(),
+                    len: chain.len(),
+                    max,
+                });
+            }
+            return Ok(None);
+        }
+        if strict
+            && let Some(ChainEntry::Invalid(token)) = chain
+                .iter()
+                .find(|entry| matches!(entry, ChainEntry::Invalid(_)))
+        {
+            return Err(RealIpError::MalformedHeader {
+                header: rule.name.clone(),
+                token: token.clone(),
+            });
+        }
+
+        let unusable = || {
+            if rule.authoritative {
+                Err(RealIpError::NoValidIp)
             } else {
-                Box::new(ips.iter().rev())
-            };
+                Ok(None)
+            }
+        };
+        let Some((index, addr)) = self.select(rule, &chain, peer, headers)? else {
+            return unusable();
+        };
+
+        // Counted hops are taken as is, like Envoy: the proxies vouched for this
+        // exact entry, whatever its address
+        let counted = matches!(
+            rule.selection,
+            ChainSelection::TrustedHops(_) | ChainSelection::HopsFromHeader(_)
+        );
+        if !counted && let Some(reason) = self.reject_reason(&addr.ip) {
+            rejected.push(Rejected {
+                addr,
+                source: Source::Header(rule.name.clone()),
+                index: Some(index),
+                reason,
+            });
+            return unusable();
+        }
+
+        Ok(Some(Extraction {
+            ip: addr.ip,
+            port: addr.port,
+            source: Source::Header(rule.name.clone()),
+            index: Some(index),
+            chain,
+            rejected: std::mem::take(rejected),
+            anomalies: Vec::new(),
+        }))
+    }
+
+    /// Look up the header of `rule`, combining repeated lines in order unless
+    /// only the first one counts.
+    fn header_value<'a, H: HeaderSource + ?Sized>(
+        headers: &'a H,
+        rule: &HeaderRule,
+    ) -> Option<Cow<'a, str>> {
+        #[cfg(feature = "http")]
+        let values = match &rule.header_name {
+            // The name may have been changed since it was interned
+            Some(interned) if interned.as_str() == rule.name => headers.get_all_interned(interned),
+            _ => headers.get_all(&rule.name),
+        };
+        #[cfg(not(feature = "http"))]
+        let values = headers.get_all(&rule.name);
+        if rule.first_only {
+            return values.first().map(|value| Cow::Borrowed(*value));
+        }
+        match values.as_slice() {
+            [] => None,
+            [value] => Some(Cow::Borrowed(*value)),
+            _ => Some(Cow::Owned(values.join(", "))),
+        }
+    }
 
-            for ip_str in ip_iter {
-                if let Ok(ip) = ip_str.parse::<IpAddr>() {
-                    return Some(ip);
+    /// Select the client from a parsed chain, returning its index and address.
+    fn select<H: HeaderSource + ?Sized>(
+        &self,
+        rule: &HeaderRule,
+        chain: &[ChainEntry],
+        peer: Option<IpAddr>,
+        headers: &H,
+    ) -> Result<Option<(usize, ClientAddr)>> {
+        let selected = match &rule.selection {
+            ChainSelection::Inherit => match &self.trusted_proxies {
+                // The peer was already checked against the extractor-wide ranges
+                Some(trusted) => Self::walk_trusted_chain(chain, trusted),
+                None if self.use_first_forwarded => Self::leftmost(chain),
+                None => Self::rightmost(chain),
+            },
+            ChainSelection::Leftmost => Self::leftmost(chain),
+            ChainSelection::Rightmost => Self::rightmost(chain),
+            ChainSelection::RightmostUntrusted(trusted) => match peer {
+                Some(peer) if trusted.contains(&peer) => Self::walk_trusted_chain(chain, trusted),
+                _ => None,
+            },
+            ChainSelection::TrustedHops(hops) => Self::hops_from_right(rule, chain, *hops)?,
+            ChainSelection::HopsFromHeader(header) => {
+                match HeaderSource::get(headers, header).map(|hops| hops.trim().parse()) {
+                    Some(Ok(hops)) => Self::hops_from_right(rule, chain, hops)?,
+                    _ => None,
                 }
             }
-        } else {
-            // Single IP address
-            if let Ok(ip) = value.parse::<IpAddr>() {
-                return Some(ip);
+            ChainSelection::Custom(selector) => selector.select(chain, peer),
+        };
+        Ok(selected)
+    }
+
+    /// The `hops`-th entry of a chain from the right. `0` selects nothing.
+    fn hops_from_right(
+        rule: &HeaderRule,
+        chain: &[ChainEntry],
+        hops: usize,
+    ) -> Result<Option<(usize, ClientAddr)>> {
+        if hops == 0 {
+            return Ok(None);
+        }
+        let index = chain
+            .len()
+            .checked_sub(hops)
+            .ok_or_else(|| RealIpError::ChainTooShort {
+                header: rule.name.clone(),
+                len: chain.len(),
+                hops,
+            })?;
+        match &chain[index] {
+            ChainEntry::Addr(addr) => Ok(Some((index, *addr))),
+            ChainEntry::Opaque(token) | ChainEntry::Invalid(token) => {
+                Err(RealIpError::MalformedHeader {
+                    header: rule.name.clone(),
+                    token: token.clone(),
+                })
             }
         }
+    }
+
+    /// The first address of a chain.
+    fn leftmost(chain: &[ChainEntry]) -> Option<(usize, ClientAddr)> {
+        chain
+            .iter()
+            .enumerate()
+            .find_map(|(index, entry)| Some((index, entry.addr()?)))
+    }
 
-        None
+    /// The last address of a chain.
+    fn rightmost(chain: &[ChainEntry]) -> Option<(usize, ClientAddr)> {
+        chain
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, entry)| Some((index, entry.addr()?)))
+    }
+
+    /// Walk a chain from the right, returning the first address outside `trusted`.
+    ///
+    /// An entry that is not an address breaks the chain of trust, so nothing is returned.
+    fn walk_trusted_chain(chain: &[ChainEntry], trusted: &CidrSet) -> Option<(usize, ClientAddr)> {
+        let mut candidate = None;
+        for (index, entry) in chain.iter().enumerate().rev() {
+            let addr = entry.addr()?;
+            candidate = Some((index, addr));
+            if !trusted.contains(&addr.ip) {
+                break;
+            }
+        }
+        candidate
     }
 
-    /// Check if IP is valid based on configuration.
-    fn is_valid_ip(&self, ip: &IpAddr) -> bool {
-        if !self.trust_private_ips && self.is_private_ip(ip) {
-            return false;
+    /// Use the fallback address, or explain why there is nothing to use.
+    fn finish(fallback: Option<ClientAddr>, rejected: Vec<Rejected>) -> Result<Extraction> {
+        match fallback {
+            Some(addr) => Ok(Extraction {
+                ip: addr.ip,
+                port: addr.port,
+                source: Source::Fallback,
+                index: None,
+                chain: Vec::new(),
+                rejected,
+                anomalies: Vec::new(),
+            }),
+            None if rejected.is_empty() => Err(RealIpError::NoSource),
+            None => Err(RealIpError::NoValidIp),
         }
-        true
     }
 
-    /// Check if IP is private/internal.
-    fn is_private_ip(&self, ip: &IpAddr) -> bool {
+    /// Check if IP is valid based on configuration, returning why it is not.
+    fn reject_reason(&self, ip: &IpAddr) -> Option<RejectReason> {
+        if self.trust_private_ips {
+            return None;
+        }
+        Self::private_reason(ip)
+    }
+
+    /// Check if IP is private/internal, returning which kind.
+    pub(crate) fn private_reason(ip: &IpAddr) -> Option<RejectReason> {
         match ip {
-            IpAddr::V4(ipv4) => ipv4.is_private() || ipv4.is_loopback() || ipv4.is_link_local(),
-            IpAddr::V6(ipv6) => {
-                ipv6.is_loopback() ||
-                (ipv6.segments()[0] & 0xfe00) == 0xfc00 || // Unique local
-                (ipv6.segments()[0] & 0xffc0) == 0xfe80 // Link local
+            IpAddr::V4(ipv4) if ipv4.is_loopback() => Some(RejectReason::Loopback),
+            IpAddr::V4(ipv4) if ipv4.is_link_local() => Some(RejectReason::LinkLocal),
+            IpAddr::V4(ipv4) if ipv4.is_private() => Some(RejectReason::Private),
+            IpAddr::V6(ipv6) if ipv6.is_loopback() => Some(RejectReason::Loopback),
+            // Link local
+            IpAddr::V6(ipv6) if (ipv6.segments()[0] & 0xffc0) == 0xfe80 => {
+                Some(RejectReason::LinkLocal)
+            }
+            // Unique local
+            IpAddr::V6(ipv6) if (ipv6.segments()[0] & 0xfe00) == 0xfc00 => {
+                Some(RejectReason::Private)
             }
+            _ => None,
         }
     }
 }
@@ -144,7 +699,7 @@ impl IpExtractor {
 ///
 /// # Arguments
 ///
-/// * `headers` - Map of HTTP headers (case-insensitive keys recommended)
+/// * `headers` - Request headers, looked up case-insensitively
 /// * `fallback_ip` - Optional fallback IP address (usually the remote socket address)
 ///
 /// # Examples
@@ -159,13 +714,19 @@ impl IpExtractor {
 /// let ip = extract_real_ip(&headers, Some("127.0.0.1".to_string()));
 /// assert_eq!(ip, Some("192.168.1.100".parse().unwrap()));
 /// ```
-pub fn extract_real_ip(headers: &HeaderMap, fallback_ip: Option<String>) -> Option<IpAddr> {
+pub fn extract_real_ip<H: HeaderSource + ?Sized>(
+    headers: &H,
+    fallback_ip: Option<String>,
+) -> Option<IpAddr> {
     let extractor = IpExtractor::default().trust_private_ips(true);
     extractor.extract(headers, fallback_ip)
 }
 
 /// Extract real IP with strict validation (no private IPs from headers).
-pub fn extract_real_ip_strict(headers: &HeaderMap, fallback_ip: Option<String>) -> Option<IpAddr> {
+pub fn extract_real_ip_strict<H: HeaderSource + ?Sized>(
+    headers: &H,
+    fallback_ip: Option<String>,
+) -> Option<IpAddr> {
     let extractor = IpExtractor::default().trust_private_ips(false);
     extractor.extract(headers, fallback_ip)
 }
@@ -217,4 +778,364 @@ mod tests {
         let ip = extract_real_ip_strict(&headers, Some("203.0.113.1".to_string()));
         assert_eq!(ip, Some("203.0.113.1".parse().unwrap()));
     }
+
+    #[test]
+    fn test_trusted_proxies_walk_from_right() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 203.0.113.9, 10.0.0.2, 10.0.0.3".to_string(),
+        );
+
+        let extractor =
+            IpExtractor::new().trusted_proxies("10.0.0.0/8".parse::<CidrSet>().unwrap());
+        let ip = extractor.extract(&headers, Some("10.0.0.1".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "198.51.100.7".to_string());
+
+        let extractor =
+            IpExtractor::new().trusted_proxies("10.0.0.0/8".parse::<CidrSet>().unwrap());
+        let ip = extractor.extract(&headers, Some("203.0.113.1".to_string()));
+        assert_eq!(ip, Some("203.0.113.1".parse().unwrap()));
+        assert_eq!(extractor.extract(&headers, None), None);
+
+        // Strict mode reports headers of every rule set, not just `rules`
+        let mut headers = HashMap::new();
+        headers.insert("x-forwarded-for".to_string(), "198.51.100.7".to_string());
+        let peer = Some("203.0.113.1".to_string());
+        for extractor in [
+            IpExtractor::new().with_rules(Vec::new()).trusted_hops(1),
+            IpExtractor::new().with_rules(Vec::new()).peer_rules(
+                CidrSet::new(),
+                vec![HeaderRule::comma_list("x-forwarded-for")],
+            ),
+        ] {
+            let extractor = extractor.trusted_proxies("10.0.0.0/8".parse::<CidrSet>().unwrap());
+            assert!(matches!(
+                extractor.try_extract(&headers, peer.clone()),
+                Err(RealIpError::UntrustedPeer(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_trusted_hops() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "192.0.2.1, 198.51.100.7, 203.0.113.9".to_string(),
+        );
+        let peer = Some("10.0.0.1".to_string());
+
+        let extractor = IpExtractor::new().trusted_hops(2);
+        assert_eq!(
+            extractor.extract(&headers, peer.clone()),
+            Some("198.51.100.7".parse().unwrap())
+        );
+
+        let extractor = IpExtractor::new().trusted_hops(0);
+        assert_eq!(
+            extractor.extract(&headers, peer.clone()),
+            Some("10.0.0.1".parse().unwrap())
+        );
+
+        // Private clients are returned, whatever `trust_private_ips` says
+        let mut private = HashMap::new();
+        private.insert(
+            "x-forwarded-for".to_string(),
+            "10.1.2.3, 192.0.2.9".to_string(),
+        );
+        let extractor = IpExtractor::new().trust_private_ips(false).trusted_hops(2);
+        assert_eq!(
+            extractor.extract(&private, peer.clone()),
+            Some("10.1.2.3".parse().unwrap())
+        );
+
+        // Too few hops: the request bypassed part of the proxy chain.
+        let extractor = IpExtractor::new().trusted_hops(4);
+        assert_eq!(extractor.extract(&headers, peer.clone()), None);
+
+        // A chain over the length limit is reported as such
+        let extractor = IpExtractor::new().trusted_hops(2).max_chain_length(2);
+        assert!(matches!(
+            extractor.try_extract(&headers, peer.clone()),
+            Err(RealIpError::ChainTooLong { len: 3, max: 2, .. })
+        ));
+        let mut extractor = IpExtractor::new().max_chain_length(2);
+        extractor.trusted_hops = Some(2);
+        assert!(matches!(
+            extractor.run(&headers, peer, false),
+            Err(RealIpError::ChainTooLong { len: 3, max: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_extract_forwarded() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "forwarded".to_string(),
+            r#"for="[2001:db8::1]:4711";proto=https, for=unknown, for=192.0.2.60;by=_hidden"#
+                .to_string(),
+        );
+
+        let ip = extract_real_ip(&headers, None);
+        assert_eq!(ip, Some("2001:db8::1".parse().unwrap()));
+
+        let extractor = IpExtractor::new().use_first_forwarded(false);
+        assert_eq!(
+            extractor.extract(&headers, None),
+            Some("192.0.2.60".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_extract_with_port() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "[2001:db8::1]:443, 203.0.113.5:51234".to_string(),
+        );
+
+        let extractor = IpExtractor::new().use_first_forwarded(false);
+        let addr = extractor.extract_addr(&headers, None).unwrap();
+        assert_eq!(addr.ip, "203.0.113.5".parse::<IpAddr>().unwrap());
+        assert_eq!(addr.port, Some(51234));
+
+        let ip = extract_real_ip(&HashMap::new(), Some("[2001:db8::2]:8080".to_string()));
+        assert_eq!(ip, Some("2001:db8::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_header_rules() {
+        let mut headers = HashMap::new();
+        headers.insert("cf-connecting-ip".to_string(), "198.51.100.4".to_string());
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "192.0.2.1, 203.0.113.9".to_string(),
+        );
+
+        let extractor = IpExtractor::new().with_rules(vec![
+            HeaderRule::single_ip("cf-connecting-ip")
+                .trusted_peers("173.245.48.0/20".parse::<CidrSet>().unwrap()),
+            HeaderRule::comma_list("x-forwarded-for").selection(ChainSelection::TrustedHops(1)),
+        ]);
+
+        // Only Cloudflare may set CF-Connecting-IP
+        let ip = extractor.extract(&headers, Some("173.245.48.1".to_string()));
+        assert_eq!(ip, Some("198.51.100.4".parse().unwrap()));
+        let ip = extractor.extract(&headers, Some("10.0.0.1".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+
+        // A custom selection sees the chain and the peer
+        let second = |chain: &[ChainEntry], peer: Option<IpAddr>| {
+            peer?;
+            Some((1, chain.get(1)?.addr()?))
+        };
+        let extractor = IpExtractor::new().with_rules(vec![
+            HeaderRule::comma_list("x-forwarded-for").selection(ChainSelection::custom(second)),
+        ]);
+        let ip = extractor.extract(&headers, Some("10.0.0.1".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(extractor.extract(&headers, None), None);
+    }
+
+    #[test]
+    fn test_extract_detailed() {
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "10.1.2.3".to_string());
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 10.0.0.2".to_string(),
+        );
+
+        let extraction = IpExtractor::new()
+            .extract_detailed(&headers, Some("10.0.0.1:4242".to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            extraction.source,
+            Source::Header("x-forwarded-for".to_string())
+        );
+        assert_eq!(extraction.index, Some(0));
+        assert_eq!(extraction.chain.len(), 2);
+        assert_eq!(extraction.rejected.len(), 1);
+        assert_eq!(
+            extraction.rejected[0].source,
+            Source::Header("x-real-ip".to_string())
+        );
+        assert_eq!(extraction.rejected[0].reason, RejectReason::Private);
+
+        let extraction = IpExtractor::new()
+            .extract_detailed(&HashMap::new(), Some("10.0.0.1:4242".to_string()))
+            .unwrap();
+        assert_eq!(extraction.source, Source::Fallback);
+        assert_eq!(extraction.port, Some(4242));
+    }
+
+    #[test]
+    fn test_try_extract_errors() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, bogus".to_string(),
+        );
+        let peer = Some("10.0.0.1".to_string());
+
+        let err = IpExtractor::new().try_extract(&headers, peer.clone());
+        assert_eq!(
+            err,
+            Err(RealIpError::MalformedHeader {
+                header: "x-forwarded-for".to_string(),
+                token: "bogus".to_string(),
+            })
+        );
+
+        let extractor =
+            IpExtractor::new().trusted_proxies("192.0.2.0/24".parse::<CidrSet>().unwrap());
+        let err = extractor.try_extract(&headers, peer.clone());
+        assert_eq!(
+            err,
+            Err(RealIpError::UntrustedPeer(Some(
+                "10.0.0.1".parse().unwrap()
+            )))
+        );
+
+        let extractor = IpExtractor::new().max_chain_length(1);
+        assert!(matches!(
+            extractor.try_extract(&headers, peer.clone()),
+            Err(RealIpError::ChainTooLong { len: 2, max: 1, .. })
+        ));
+        assert_eq!(
+            extractor.extract(&headers, peer),
+            Some("10.0.0.1".parse().unwrap())
+        );
+
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 203.0.113.9".to_string(),
+        );
+        let extractor = IpExtractor::new().trusted_hops(3);
+        assert!(matches!(
+            extractor.try_extract(&headers, None),
+            Err(RealIpError::ChainTooShort {
+                len: 2,
+                hops: 3,
+                ..
+            })
+        ));
+
+        assert_eq!(
+            IpExtractor::new().try_extract(&HashMap::new(), None),
+            Err(RealIpError::NoSource)
+        );
+    }
+
+    #[test]
+    fn test_conflicting_headers() {
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "198.51.100.1".to_string());
+        headers.insert("cf-connecting-ip".to_string(), "198.51.100.2".to_string());
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.2, 10.0.0.2".to_string(),
+        );
+
+        let extraction = IpExtractor::new().extract_detailed(&headers, None).unwrap();
+        assert_eq!(extraction.ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert!(extraction.anomalies.contains(&Anomaly::HeaderDisagreement {
+            first: "x-real-ip".to_string(),
+            first_ip: "198.51.100.1".parse().unwrap(),
+            second: "cf-connecting-ip".to_string(),
+            second_ip: "198.51.100.2".parse().unwrap(),
+        }));
+        assert!(extraction.anomalies.contains(&Anomaly::NotInChain {
+            header: "x-real-ip".to_string(),
+            ip: "198.51.100.1".parse().unwrap(),
+            chain_header: "x-forwarded-for".to_string(),
+        }));
+
+        let extractor = IpExtractor::new().conflict_policy(ConflictPolicy::RequireConsensus);
+        assert!(matches!(
+            extractor.try_extract(&headers, None),
+            Err(RealIpError::ConflictingHeaders(
+                Anomaly::HeaderDisagreement { .. }
+            ))
+        ));
+        assert_eq!(extractor.extract(&headers, None), None);
+
+        headers.remove("cf-connecting-ip");
+        headers.insert(
+            "x-real-ip".to_string(),
+            "198.51.100.2, 198.51.100.2".to_string(),
+        );
+        let extraction = extractor.extract_detailed(&headers, None).unwrap();
+        assert_eq!(
+            extraction.anomalies,
+            vec![Anomaly::DuplicateHeader {
+                header: "x-real-ip".to_string(),
+                count: 2,
+            }]
+        );
+        let extractor = IpExtractor::new().conflict_policy(ConflictPolicy::Reject);
+        assert!(extractor.try_extract(&headers, None).is_err());
+    }
+
+    #[test]
+    fn test_repeated_headers() {
+        let headers = vec![
+            ("X-Forwarded-For".to_string(), "203.0.113.9".to_string()),
+            ("x-forwarded-for".to_string(), "198.51.100.2".to_string()),
+            ("x-real-ip".to_string(), "198.51.100.2".to_string()),
+            ("X-Real-IP".to_string(), "198.51.100.2".to_string()),
+        ];
+
+        let extraction = IpExtractor::new()
+            .with_headers(vec!["x-forwarded-for".to_string(), "x-real-ip".to_string()])
+            .use_first_forwarded(false)
+            .extract_detailed(&headers, None)
+            .unwrap();
+        assert_eq!(extraction.ip, "198.51.100.2".parse::<IpAddr>().unwrap());
+        assert_eq!(extraction.index, Some(1));
+        assert_eq!(
+            extraction.anomalies,
+            vec![Anomaly::DuplicateHeader {
+                header: "x-real-ip".to_string(),
+                count: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_peer_rules() {
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "198.51.100.7".to_string());
+        headers.insert("x-forwarded-for".to_string(), "203.0.113.9".to_string());
+
+        let extractor = IpExtractor::new()
+            .peer_rules(
+                "192.0.2.0/24".parse::<CidrSet>().unwrap(),
+                vec![HeaderRule::single_ip("x-real-ip")],
+            )
+            .peer_rules(
+                "192.0.2.0/24, 198.51.100.0/24".parse::<CidrSet>().unwrap(),
+                vec![HeaderRule::comma_list("x-forwarded-for")],
+            )
+            .peer_rules("203.0.113.0/24".parse::<CidrSet>().unwrap(), Vec::new());
+
+        // The first matching entry wins
+        let ip = |peer: &str| extractor.extract(&headers, Some(peer.to_string())).unwrap();
+        assert_eq!(ip("192.0.2.1"), "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(ip("198.51.100.1"), "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(ip("203.0.113.1"), "203.0.113.1".parse::<IpAddr>().unwrap());
+        assert_eq!(ip("8.8.8.8"), "8.8.8.8".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            extractor.try_extract(&headers, Some("8.8.8.8".to_string())),
+            Err(RealIpError::UntrustedPeer(Some("8.8.8.8".parse().unwrap())))
+        );
+    }
 }
@@ -0,0 +1,362 @@
+/* src/forwarded.rs */
+
+//! Parser for the RFC 7239 `Forwarded` header.
+//!
+//! ```rust
+//! use real::forwarded::{self, NodeName};
+//!
+//! let elements = forwarded::parse(r#"for=192.0.2.60;proto=http;by=203.0.113.43, for="[2001:db8::1]:4711""#).unwrap();
+//! assert_eq!(elements.len(), 2);
+//! assert_eq!(elements[0].proto.as_deref(), Some("http"));
+//! assert_eq!(elements[1].forwarded_for.as_ref().unwrap().name, NodeName::Ip("2001:db8::1".parse().unwrap()));
+//! ```
+
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::addr::ClientAddr;
+use crate::error::{RealIpError, Result};
+
+/// One comma-separated element of a `Forwarded` header, describing a single proxy hop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedElement {
+    /// The `for` parameter: the client or proxy that made the request to this hop.
+    pub forwarded_for: Option<Node>,
+    /// The `by` parameter: the interface where the request came in to the proxy.
+    pub by: Option<Node>,
+    /// The `host` parameter: the original `Host` request header.
+    pub host: Option<String>,
+    /// The `proto` parameter: the protocol used to make the request.
+    pub proto: Option<String>,
+    /// Any other parameters, with lowercased names.
+    pub extensions: Vec<(String, String)>,
+}
+
+/// A node identifier used by the `for` and `by` parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// The node name.
+    pub name: NodeName,
+    /// The optional node port.
+    pub port: Option<NodePort>,
+}
+
+/// The name part of a node identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeName {
+    /// An IPv4 or IPv6 address.
+    Ip(IpAddr),
+    /// The `unknown` identifier, used when the proxy does not know the address.
+    Unknown,
+    /// An obfuscated identifier such as `_hidden`, including the leading underscore.
+    Obfuscated(String),
+}
+
+/// The port part of a node identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodePort {
+    /// A numeric port.
+    Port(u16),
+    /// An obfuscated port such as `_8080`, including the leading underscore.
+    Obfuscated(String),
+}
+
+impl Node {
+    /// The IP address of this node, if it is not `unknown` or obfuscated.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self.name {
+            NodeName::Ip(ip) => Some(ip),
+            _ => None,
+        }
+    }
+
+    /// The numeric port of this node, if present and not obfuscated.
+    pub fn port(&self) -> Option<u16> {
+        match self.port {
+            Some(NodePort::Port(port)) => Some(port),
+            _ => None,
+        }
+    }
+
+    /// The address and numeric port of this node, if it has an IP address.
+    pub fn addr(&self) -> Option<ClientAddr> {
+        self.ip().map(|ip| ClientAddr {
+            ip,
+            port: self.port(),
+        })
+    }
+}
+
+impl FromStr for Node {
+    type Err = RealIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || RealIpError::InvalidForwarded(s.to_string());
+
+        let (name, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (v6, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            let ip = v6.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match rest {
+                "" => None,
+                _ => Some(rest.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (NodeName::Ip(IpAddr::V6(ip)), port)
+        } else if let Ok(ip) = s.parse::<Ipv6Addr>() {
+            // Not allowed by the grammar, but unambiguous without a port
+            (NodeName::Ip(IpAddr::V6(ip)), None)
+        } else {
+            let (name, port) = match s.split_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (s, None),
+            };
+            let name = if name.eq_ignore_ascii_case("unknown") {
+                NodeName::Unknown
+            } else if is_obfuscated(name) {
+                NodeName::Obfuscated(name.to_string())
+            } else {
+                NodeName::Ip(name.parse().map_err(|_| invalid())?)
+            };
+            (name, port)
+        };
+
+        let port = match port {
+            None => None,
+            Some(port) if is_obfuscated(port) => Some(NodePort::Obfuscated(port.to_string())),
+            Some(port) if !port.is_empty() && port.len() <= 5 => {
+                Some(NodePort::Port(port.parse().map_err(|_| invalid())?))
+            }
+            Some(_) => return Err(invalid()),
+        };
+
+        Ok(Self { name, port })
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            NodeName::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip)?,
+            NodeName::Ip(IpAddr::V4(ip)) => write!(f, "{}", ip)?,
+            NodeName::Unknown => f.write_str("unknown")?,
+            NodeName::Obfuscated(name) => f.write_str(name)?,
+        }
+        match &self.port {
+            Some(NodePort::Port(port)) => write!(f, ":{}", port),
+            Some(NodePort::Obfuscated(port)) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a `Forwarded` header value into its elements, in order.
+///
+/// Values may be tokens or quoted strings. For compatibility with proxies that
+/// forget to quote them, unquoted values may also contain `:`, `[` and `]`.
+/// Each parameter may appear at most once per element.
+pub fn parse(value: &str) -> Result<Vec<ForwardedElement>> {
+    let mut parser = Parser {
+        input: value,
+        pos: 0,
+    };
+    let mut elements = Vec::new();
+
+    loop {
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => break,
+            // Empty list elements are allowed
+            Some(',') => parser.bump(),
+            Some(_) => {
+                elements.push(parser.element()?);
+                parser.skip_whitespace();
+                match parser.peek() {
+                    None => break,
+                    Some(',') => parser.bump(),
+                    Some(_) => return Err(parser.error()),
+                }
+            }
+        }
+    }
+
+    Ok(elements)
+}
+
+/// Collect the `for` nodes of all elements, from the client to the closest proxy.
+pub fn parse_for_chain(value: &str) -> Result<Vec<Node>> {
+    Ok(parse(value)?
+        .into_iter()
+        .filter_map(|element| element.forwarded_for)
+        .collect())
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn error(&self) -> RealIpError {
+        RealIpError::InvalidForwarded(self.input.to_string())
+    }
+
+    fn element(&mut self) -> Result<ForwardedElement> {
+        let mut element = ForwardedElement::default();
+
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(',') => return Ok(element),
+                // Empty pairs are allowed
+                Some(';') => {
+                    self.bump();
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            let name = self.token(false)?.to_ascii_lowercase();
+            if self.peek() != Some('=') {
+                return Err(self.error());
+            }
+            self.bump();
+            let value = match self.peek() {
+                Some('"') => self.quoted_string()?,
+                _ => self.token(true)?.to_string(),
+            };
+
+            let duplicate = match name.as_str() {
+                "for" => element.forwarded_for.replace(value.parse()?).is_some(),
+                "by" => element.by.replace(value.parse()?).is_some(),
+                "host" => element.host.replace(value).is_some(),
+                "proto" => element.proto.replace(value).is_some(),
+                _ => {
+                    let duplicate = element.extensions.iter().any(|(n, _)| *n == name);
+                    element.extensions.push((name, value));
+                    duplicate
+                }
+            };
+            if duplicate {
+                return Err(self.error());
+            }
+
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(',') => return Ok(element),
+                Some(';') => self.bump(),
+                Some(_) => return Err(self.error()),
+            }
+        }
+    }
+
+    fn token(&mut self, lenient: bool) -> Result<&str> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(is_tchar(c) || lenient && matches!(c, ':' | '[' | ']')) {
+                break;
+            }
+            self.bump();
+        }
+        if self.pos == start {
+            return Err(self.error());
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn quoted_string(&mut self) -> Result<String> {
+        // Skip the opening quote
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error()),
+                Some('"') => {
+                    self.bump();
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    self.bump();
+                    value.push(self.peek().ok_or_else(|| self.error())?);
+                    self.bump();
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.bump();
+                }
+            }
+        }
+    }
+}
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn is_obfuscated(s: &str) -> bool {
+    s.len() > 1
+        && s.starts_with('_')
+        && s[1..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multiple_elements() {
+        let elements =
+            parse("for=192.0.2.60;proto=http;by=203.0.113.43, for=198.51.100.17;host=example.com")
+                .unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(
+            elements[0].forwarded_for.as_ref().unwrap().ip(),
+            Some("192.0.2.60".parse().unwrap())
+        );
+        assert_eq!(
+            elements[0].by.as_ref().unwrap().ip(),
+            Some("203.0.113.43".parse().unwrap())
+        );
+        assert_eq!(elements[0].proto.as_deref(), Some("http"));
+        assert_eq!(elements[1].host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn test_parse_nodes() {
+        let chain = parse_for_chain(
+            r#"for="[2001:db8:cafe::17]:4711", For=unknown, for=_hidden;by="_SEVKISEK:_8080""#,
+        )
+        .unwrap();
+        assert_eq!(chain[0].ip(), Some("2001:db8:cafe::17".parse().unwrap()));
+        assert_eq!(chain[0].port(), Some(4711));
+        assert_eq!(chain[1].name, NodeName::Unknown);
+        assert_eq!(chain[2].name, NodeName::Obfuscated("_hidden".to_string()));
+        assert_eq!(chain[0].to_string(), "[2001:db8:cafe::17]:4711");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("for=192.0.2.60;for=192.0.2.61").is_err());
+        assert!(parse(r#"for="192.0.2.60"#).is_err());
+        assert!(parse("for=not-an-ip").is_err());
+        assert!(parse("for").is_err());
+        assert!(parse("for=[2001:db8::1]:99999").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+/* src/headers.rs */
+
+use std::collections::HashMap;
+
+/// Read access to request headers, without copying them.
+///
+/// Lookups are case-insensitive and return every instance of a header in the
+/// order it was received, so repeated `X-Forwarded-For` lines can be combined as
+/// RFC 9110 requires.
+///
+/// ```rust
+/// use real::{HeaderSource, IpExtractor};
+///
+/// let headers = vec![
+///     ("X-Forwarded-For".to_string(), "203.0.113.9".to_string()),
+///     ("x-forwarded-for".to_string(), "198.51.100.2".to_string()),
+/// ];
+/// assert_eq!(headers.get_all("x-forwarded-for"), ["203.0.113.9", "198.51.100.2"]);
+///
+/// let ip = IpExtractor::new().use_first_forwarded(false).extract(&headers, None);
+/// assert_eq!(ip, Some("198.51.100.2".parse().unwrap()));
+/// ```
+pub trait HeaderSource {
+    /// All values of the header `name`, in order. Empty when it is absent.
+    fn get_all(&self, name: &str) -> Vec<&str>;
+
+    /// The first value of the header `name`.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).into_iter().next()
+    }
+
+    /// All values of the header `name`, for sources that can look up an
+    /// interned name without parsing it again.
+    #[cfg(feature = "http")]
+    fn get_all_interned(&self, name: &http::HeaderName) -> Vec<&str> {
+        self.get_all(name.as_str())
+    }
+}
+
+impl<T: HeaderSource + ?Sized> HeaderSource for &T {
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        (**self).get_all(name)
+    }
+
+    #[cfg(feature = "http")]
+    fn get_all_interned(&self, name: &http::HeaderName) -> Vec<&str> {
+        (**self).get_all_interned(name)
+    }
+}
+
+/// A key spelled as `name`, or in lowercase, is found without scanning the
+/// map. Otherwise, keys differing only in case are all matched, in unspecified
+/// order.
+impl<S: std::hash::BuildHasher> HeaderSource for HashMap<String, String, S> {
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        if let Some(value) = direct_get(self, name) {
+            return vec![value];
+        }
+        self.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        direct_get(self, name).or_else(|| {
+            self.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        })
+    }
+}
+
+/// Look `name` up as spelled, then in lowercase.
+fn direct_get<'a, S: std::hash::BuildHasher>(
+    map: &'a HashMap<String, String, S>,
+    name: &str,
+) -> Option<&'a str> {
+    if let Some(value) = map.get(name) {
+        return Some(value);
+    }
+    if !name.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    map.get(&name.to_ascii_lowercase()).map(String::as_str)
+}
+
+impl HeaderSource for [(String, String)] {
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        self.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+impl HeaderSource for Vec<(String, String)> {
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        self.as_slice().get_all(name)
+    }
+}
+
+/// Values that are not visible ASCII are skipped.
+#[cfg(feature = "http")]
+impl HeaderSource for http::HeaderMap {
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        self.get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn get_all_interned(&self, name: &http::HeaderName) -> Vec<&str> {
+        self.get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_insensitive_lookup() {
+        let mut headers = HashMap::new();
+        headers.insert("X-Real-IP".to_string(), "192.0.2.1".to_string());
+        assert_eq!(headers.get_all("x-real-ip"), ["192.0.2.1"]);
+        assert_eq!(HeaderSource::get(&headers, "X-REAL-IP"), Some("192.0.2.1"));
+        assert!(headers.get_all("x-forwarded-for").is_empty());
+
+        headers.insert("x-forwarded-for".to_string(), "192.0.2.2".to_string());
+        assert_eq!(headers.get_all("X-Forwarded-For"), ["192.0.2.2"]);
+        assert_eq!(HeaderSource::get(&headers, "forwarded"), None);
+    }
+
+    #[test]
+    fn test_repeated_headers_keep_order() {
+        let headers = vec![
+            ("x-forwarded-for".to_string(), "192.0.2.1".to_string()),
+            ("host".to_string(), "example.com".to_string()),
+            ("X-Forwarded-For".to_string(), "192.0.2.2".to_string()),
+        ];
+        assert_eq!(
+            headers.get_all("x-forwarded-for"),
+            ["192.0.2.1", "192.0.2.2"]
+        );
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_http_header_map() {
+        let mut headers = http::HeaderMap::new();
+        headers.append("x-forwarded-for", "192.0.2.1".parse().unwrap());
+        headers.append("x-forwarded-for", "192.0.2.2".parse().unwrap());
+        assert_eq!(
+            HeaderSource::get_all(&headers, "X-Forwarded-For"),
+            ["192.0.2.1", "192.0.2.2"]
+        );
+    }
+}
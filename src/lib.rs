@@ -9,6 +9,18 @@
 //!
 //! - Extract real IP from various HTTP headers
 //! - Support for X-Real-IP, X-Forwarded-For, CF-Connecting-IP headers
+//! - Per-header rules for format, chain selection and trusted peers
+//! - Composable strategies, such as `RightmostTrustedRange`, tried in order by `Chain`
+//! - RFC 7239 `Forwarded` header parsing
+//! - Trusted proxy ranges with right-to-left `X-Forwarded-For` walking
+//! - Accepts `ip:port` and `[ipv6]:port` forms, keeping the port available
+//! - Detailed extraction results reporting the source header, chain position and rejected candidates
+//! - Header spoofing and conflict detection with a configurable policy
+//! - Borrowed, case-insensitive header access that keeps repeated headers, including `http::HeaderMap` via the `http` feature
+//! - HAProxy PROXY protocol v1/v2 parsing, with a tokio listener wrapper via the `tokio` feature
+//! - Provider presets, such as `IpExtractor::cloudflare()`, that only trust the provider's addresses
+//! - Compatibility modes reproducing nginx's realip module, Apache's mod_remoteip and Envoy's original IP detection from their configuration
+//! - Validated extractors built once with `IpExtractor::build()` and shared cheaply
 //! - Fallback to remote socket address
 //! - Optional Axum middleware and extractor integration via the `axum` feature
 //!
@@ -29,14 +41,40 @@
 //! assert_eq!(ip, Some("192.168.1.100".parse().unwrap()));
 //! ```
 
+pub mod addr;
+pub mod cidr;
+pub mod compat;
+pub mod compiled;
 pub mod error;
+pub mod extraction;
 pub mod extractor;
+pub mod forwarded;
+pub mod headers;
+pub mod presets;
+pub mod proxy_protocol;
+pub mod rule;
+pub mod strategy;
 
 #[cfg(feature = "axum")]
 pub mod middleware;
 
-pub use error::{RealIpError, Result};
-pub use extractor::{HeaderMap, IpExtractor, extract_real_ip, extract_real_ip_strict};
+pub use addr::ClientAddr;
+pub use cidr::{Cidr, CidrSet};
+pub use compiled::CompiledExtractor;
+pub use error::{ConfigError, RealIpError, Result};
+pub use extraction::{Anomaly, Extraction, RejectReason, Rejected, Source};
+pub use extractor::{
+    ConflictPolicy, HeaderMap, IpExtractor, extract_real_ip, extract_real_ip_strict,
+};
+pub use headers::HeaderSource;
+pub use rule::{
+    ChainEntry, ChainSelection, ChainSelector, Condition, HeaderFormat, HeaderParser, HeaderRule,
+    PeerRule,
+};
+pub use strategy::{
+    Chain, LeftmostNonPrivate, RemoteAddr, RightmostNonPrivate, RightmostTrustedCount,
+    RightmostTrustedRange, SingleIpHeader, Strategy,
+};
 
 #[cfg(feature = "axum")]
 pub use middleware::{RealIp, RealIpLayer, RealIpService};
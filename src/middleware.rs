@@ -2,16 +2,21 @@
 
 use axum::{
     extract::{ConnectInfo, FromRequestParts},
-    http::{Request, Response, request::Parts},
+    http::{Extensions, Request, Response, request::Parts},
 };
 use futures_util::future::BoxFuture;
 use std::{
+    fmt,
     net::{IpAddr, SocketAddr},
+    sync::Arc,
     task::{Context, Poll},
 };
 use tower::{Layer, Service};
 
-use crate::extractor::{HeaderMap as InnerHeaderMap, IpExtractor};
+use crate::compiled::CompiledExtractor;
+use crate::extractor::IpExtractor;
+use crate::proxy_protocol::ProxiedAddr;
+use crate::strategy::Strategy;
 
 /// Extension that holds the extracted real IP address.
 #[derive(Debug, Clone)]
@@ -26,17 +31,37 @@ impl RealIp {
 
 /// A layer that extracts the real IP address from a request and inserts it into
 /// the request extensions, making it available for subsequent handlers and extractors.
+///
+/// The full [`Extraction`](crate::Extraction) is inserted next to [`RealIp`], so
+/// handlers can log which header or chain position produced the address.
+/// Behind a [`ProxyProtocolListener`](crate::proxy_protocol::ProxyProtocolListener),
+/// the [`ProxyTlvs`](crate::proxy_protocol::ProxyTlvs) of the connection are
+/// inserted as well. With a [`Strategy`], only [`RealIp`] is inserted.
 #[derive(Debug, Clone)]
 pub struct RealIpLayer {
-    extractor: IpExtractor,
+    extractor: Extractor,
+}
+
+/// What the layer extracts the address with.
+#[derive(Clone)]
+enum Extractor {
+    Config(CompiledExtractor),
+    Strategy(Arc<dyn Strategy>),
+}
+
+impl fmt::Debug for Extractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(extractor) => f.debug_tuple("Config").field(extractor).finish(),
+            Self::Strategy(_) => f.write_str("Strategy(..)"),
+        }
+    }
 }
 
 impl Default for RealIpLayer {
     fn default() -> Self {
-        Self {
-            // Default behavior: trust private IPs from headers.
-            extractor: IpExtractor::default().trust_private_ips(true),
-        }
+        // Default behavior: trust private IPs from headers.
+        Self::with_extractor(IpExtractor::default().trust_private_ips(true))
     }
 }
 
@@ -47,16 +72,54 @@ impl RealIpLayer {
     }
 
     /// Create a new real IP layer with custom extractor configuration.
+    ///
+    /// The configuration is compiled but not validated; use
+    /// [`with_compiled`](Self::with_compiled) to reject bad configurations at startup.
     pub fn with_extractor(extractor: IpExtractor) -> Self {
-        Self { extractor }
+        Self::with_compiled(extractor.compile())
     }
 
-    /// Create a strict layer that doesn't trust private IPs from headers.
-    pub fn strict() -> Self {
+    /// Create a new real IP layer sharing an extractor from [`IpExtractor::build`].
+    ///
+    /// ```rust
+    /// use real::{IpExtractor, RealIpLayer};
+    ///
+    /// let extractor = IpExtractor::new().trusted_hops(1).build().unwrap();
+    /// let layer = RealIpLayer::with_compiled(extractor);
+    /// ```
+    pub fn with_compiled(extractor: CompiledExtractor) -> Self {
+        Self {
+            extractor: Extractor::Config(extractor),
+        }
+    }
+
+    /// Create a new real IP layer choosing the address with `strategy`.
+    ///
+    /// ```rust
+    /// use real::{Chain, RealIpLayer, RemoteAddr, RightmostTrustedCount};
+    ///
+    /// let layer = RealIpLayer::with_strategy(Chain::new().or(RightmostTrustedCount::new(1)).or(RemoteAddr));
+    /// ```
+    pub fn with_strategy(strategy: impl Strategy + 'static) -> Self {
         Self {
-            extractor: IpExtractor::default().trust_private_ips(false),
+            extractor: Extractor::Strategy(Arc::new(strategy)),
         }
     }
+
+    /// Create a layer for services behind a fixed number of proxies that append
+    /// to X-Forwarded-For. See [`IpExtractor::trusted_hops`].
+    pub fn trusted_hops(hops: usize) -> Self {
+        Self::with_extractor(
+            IpExtractor::default()
+                .trust_private_ips(true)
+                .trusted_hops(hops),
+        )
+    }
+
+    /// Create a strict layer that doesn't trust private IPs from headers.
+    pub fn strict() -> Self {
+        Self::with_extractor(IpExtractor::default().trust_private_ips(false))
+    }
 }
 
 impl<S> Layer<S> for RealIpLayer {
@@ -74,7 +137,7 @@ impl<S> Layer<S> for RealIpLayer {
 #[derive(Debug, Clone)]
 pub struct RealIpService<S> {
     inner: S,
-    extractor: IpExtractor,
+    extractor: Extractor,
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RealIpService<S>
@@ -97,15 +160,26 @@ where
         let extractor = self.extractor.clone();
 
         Box::pin(async move {
-            let fallback_ip = req
-                .extensions()
-                .get::<ConnectInfo<SocketAddr>>()
-                .map(|connect_info| connect_info.0.ip().to_string());
-
-            let header_map = headers_to_map(req.headers());
+            let fallback_ip = connect_addr(req.extensions());
+            if let Some(ConnectInfo(addr)) = req.extensions().get::<ConnectInfo<ProxiedAddr>>() {
+                let tlvs = addr.header.tlvs.clone();
+                req.extensions_mut().insert(tlvs);
+            }
 
-            if let Some(real_ip) = extractor.extract(&header_map, fallback_ip) {
-                req.extensions_mut().insert(RealIp(real_ip));
+            match &extractor {
+                Extractor::Config(extractor) => {
+                    if let Some(extraction) = extractor.extract_detailed(req.headers(), fallback_ip)
+                    {
+                        req.extensions_mut().insert(RealIp(extraction.ip));
+                        req.extensions_mut().insert(extraction);
+                    }
+                }
+                Extractor::Strategy(strategy) => {
+                    let peer = fallback_ip.and_then(|peer| peer.parse().ok());
+                    if let Some(addr) = strategy.client_addr(req.headers(), peer) {
+                        req.extensions_mut().insert(RealIp(addr.ip));
+                    }
+                }
             }
 
             inner.call(req).await
@@ -113,15 +187,14 @@ where
     }
 }
 
-/// Convert Axum headers to our internal header map format.
-fn headers_to_map(headers: &axum::http::HeaderMap) -> InnerHeaderMap {
-    let mut map = InnerHeaderMap::new();
-    for (name, value) in headers.iter() {
-        if let Ok(value_str) = value.to_str() {
-            map.insert(name.as_str().to_lowercase(), value_str.to_string());
-        }
+/// The connection address to fall back to, preferring the client named by a PROXY header.
+fn connect_addr(extensions: &Extensions) -> Option<String> {
+    if let Some(ConnectInfo(addr)) = extensions.get::<ConnectInfo<ProxiedAddr>>() {
+        return Some(addr.client().to_string());
     }
-    map
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|connect_info| connect_info.0.to_string())
 }
 
 /// Axum extractor for the real IP address.
@@ -138,15 +211,10 @@ where
             return Ok(real_ip.clone());
         }
 
-        let fallback_ip = parts
-            .extensions
-            .get::<ConnectInfo<SocketAddr>>()
-            .map(|connect_info| connect_info.0.ip().to_string());
-
-        let header_map = headers_to_map(&parts.headers);
+        let fallback_ip = connect_addr(&parts.extensions);
 
         let extractor = IpExtractor::default().trust_private_ips(false);
-        if let Some(real_ip) = extractor.extract(&header_map, fallback_ip) {
+        if let Some(real_ip) = extractor.extract(&parts.headers, fallback_ip) {
             return Ok(RealIp(real_ip));
         }
 
@@ -0,0 +1,17 @@
+/* src/presets.rs */
+
+//! Extractor presets for CDNs, cloud load balancers and hosting platforms.
+//!
+//! Each preset only honors the provider's headers when the socket peer is one
+//! of the provider's own addresses, so clients cannot spoof them by talking to
+//! the origin directly. Bundled address ranges are snapshots; reload them from
+//! the provider's published lists to stay current.
+
+pub mod akamai;
+pub mod aws;
+pub mod azure;
+pub mod cloudflare;
+pub mod fastly;
+pub mod gcp;
+pub mod kubernetes;
+pub mod paas;
@@ -0,0 +1,96 @@
+/* src/presets/akamai.rs */
+
+//! Akamai, which sends the client address in `True-Client-IP`.
+//!
+//! Akamai's origin-facing ranges depend on the contract, such as a Site Shield
+//! map, so they are not bundled; load them with [`CidrSet::from_file`].
+//!
+//! Each Akamai server a request passes through appends its peer to
+//! `X-Forwarded-For` and counts itself in `Akamai-Origin-Hop`, so the client is
+//! that many entries from the right. The chain is cross-checked against
+//! `True-Client-IP` and any disagreement is reported as an anomaly; combine with
+//! [`ConflictPolicy::RequireConsensus`](crate::ConflictPolicy::RequireConsensus)
+//! to reject such requests.
+//!
+//! ```rust
+//! use real::{CidrSet, IpExtractor};
+//! use std::collections::HashMap;
+//!
+//! let ranges: CidrSet = "23.32.0.0/11".parse().unwrap();
+//! let mut headers = HashMap::new();
+//! headers.insert("true-client-ip".to_string(), "198.51.100.7".to_string());
+//! headers.insert("x-forwarded-for".to_string(), "198.51.100.7, 23.33.0.1".to_string());
+//! headers.insert("akamai-origin-hop".to_string(), "2".to_string());
+//!
+//! let extraction = IpExtractor::akamai(ranges)
+//!     .extract_detailed(&headers, Some("23.34.0.1".to_string()))
+//!     .unwrap();
+//! assert_eq!(extraction.ip, "198.51.100.7".parse::<std::net::IpAddr>().unwrap());
+//! assert!(extraction.anomalies.is_empty());
+//! ```
+//!
+//! [`CidrSet::from_file`]: crate::CidrSet::from_file
+
+use crate::cidr::CidrSet;
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainSelection, HeaderRule};
+
+/// The header rules of the preset, honored only from peers inside `ranges`.
+pub fn rules(ranges: CidrSet) -> Vec<HeaderRule> {
+    vec![
+        HeaderRule::single_ip("true-client-ip").trusted_peers(ranges.clone()),
+        HeaderRule::comma_list("x-forwarded-for")
+            .trusted_peers(ranges)
+            .selection(ChainSelection::HopsFromHeader(
+                "akamai-origin-hop".to_string(),
+            )),
+    ]
+}
+
+impl IpExtractor {
+    /// Preset for origins behind Akamai servers inside `ranges`.
+    pub fn akamai(ranges: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(rules(ranges.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Anomaly, ConflictPolicy};
+    use std::collections::HashMap;
+
+    const EDGE: &str = "23.34.0.1";
+
+    #[test]
+    fn test_origin_hop_cross_check() {
+        let ranges: CidrSet = "23.32.0.0/11".parse().unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("true-client-ip".to_string(), "192.0.2.1".to_string());
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "192.0.2.1, 198.51.100.7, 23.33.0.1".to_string(),
+        );
+        headers.insert("akamai-origin-hop".to_string(), "2".to_string());
+
+        let extraction = IpExtractor::akamai(ranges.clone())
+            .extract_detailed(&headers, Some(EDGE.to_string()))
+            .unwrap();
+        assert!(matches!(
+            extraction.anomalies[0],
+            Anomaly::HeaderDisagreement { .. }
+        ));
+        let strict =
+            IpExtractor::akamai(ranges.clone()).conflict_policy(ConflictPolicy::RequireConsensus);
+        assert!(
+            strict
+                .try_extract(&headers, Some(EDGE.to_string()))
+                .is_err()
+        );
+
+        // Without True-Client-IP, the chain is used
+        headers.remove("true-client-ip");
+        let ip = IpExtractor::akamai(ranges).extract(&headers, Some(EDGE.to_string()));
+        assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+    }
+}
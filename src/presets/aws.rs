@@ -0,0 +1,188 @@
+/* src/presets/aws.rs */
+
+//! AWS Application and Classic Load Balancers, optionally behind CloudFront.
+//!
+//! The load balancer appends the address it saw to `X-Forwarded-For`. When it
+//! sits behind CloudFront, that address is a CloudFront edge server, and
+//! CloudFront sends the viewer as `ip:port` in `CloudFront-Viewer-Address`.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use std::collections::HashMap;
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "192.0.2.1, 198.51.100.7".to_string());
+//!
+//! // The load balancer connects from inside the VPC
+//! let ip = IpExtractor::aws().extract(&headers, Some("10.0.1.20".to_string()));
+//! assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+//! ```
+
+#[cfg(feature = "json")]
+use std::io;
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use crate::cidr::CidrSet;
+#[cfg(feature = "json")]
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainSelection, Condition, HeaderRule};
+
+/// Private IPv4 ranges, where load balancers in a VPC usually live.
+pub const VPC_RANGES: &[&str] = &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"];
+
+/// The header the load balancer appends to.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The default VPC ranges.
+pub fn vpc_ranges() -> CidrSet {
+    VPC_RANGES
+        .iter()
+        .map(|range| range.parse().expect("bundled range is valid"))
+        .collect()
+}
+
+/// Collect the prefixes of `service`, such as `CLOUDFRONT`, from the contents of
+/// AWS's [`ip-ranges.json`](https://ip-ranges.amazonaws.com/ip-ranges.json).
+#[cfg(feature = "json")]
+pub fn parse_ip_ranges(json: &str, service: &str) -> Result<CidrSet> {
+    use serde_json::Value;
+
+    let invalid = |message: String| RealIpError::InvalidRangeList(message);
+    let doc: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+
+    let mut set = CidrSet::new();
+    for (list, key) in [("prefixes", "ip_prefix"), ("ipv6_prefixes", "ipv6_prefix")] {
+        let entries = doc
+            .get(list)
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid(format!("missing {}", list)))?;
+        for entry in entries {
+            if entry.get("service").and_then(Value::as_str) != Some(service) {
+                continue;
+            }
+            let prefix = entry
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid(format!("entry without {}", key)))?;
+            set.insert(prefix.parse()?);
+        }
+    }
+    Ok(set)
+}
+
+/// Load the prefixes of `service` from a local copy of `ip-ranges.json`.
+#[cfg(feature = "json")]
+pub fn load_ip_ranges(path: impl AsRef<Path>, service: &str) -> io::Result<CidrSet> {
+    parse_ip_ranges(&std::fs::read_to_string(path)?, service)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Load the `CLOUDFRONT` prefixes from a local copy of `ip-ranges.json`.
+#[cfg(feature = "json")]
+pub fn load_cloudfront_ranges(path: impl AsRef<Path>) -> io::Result<CidrSet> {
+    load_ip_ranges(path, "CLOUDFRONT")
+}
+
+/// The header rules of the preset.
+///
+/// Headers are only honored from load balancers inside `vpc`. The
+/// `X-Forwarded-For` chain is walked from the right past `vpc` and `cloudfront`
+/// addresses. `CloudFront-Viewer-Address` is preferred, for its port, but only
+/// when the load balancer saw a `cloudfront` address.
+pub fn rules(vpc: CidrSet, cloudfront: CidrSet) -> Vec<HeaderRule> {
+    let proxies: CidrSet = vpc.iter().chain(cloudfront.iter()).copied().collect();
+    vec![
+        HeaderRule::ip_port("cloudfront-viewer-address")
+            .trusted_peers(vpc.clone())
+            .when(Condition::LastHopIn {
+                header: FORWARDED_FOR.to_string(),
+                ranges: cloudfront,
+            }),
+        HeaderRule::comma_list(FORWARDED_FOR)
+            .trusted_peers(vpc)
+            .selection(ChainSelection::RightmostUntrusted(proxies)),
+    ]
+}
+
+impl IpExtractor {
+    /// Preset for services behind an AWS load balancer in a VPC with private addresses.
+    ///
+    /// No CloudFront ranges are trusted, so behind CloudFront the edge server is
+    /// returned; use [`aws_with_ranges`](Self::aws_with_ranges) with
+    /// [`load_cloudfront_ranges`] there.
+    pub fn aws() -> Self {
+        Self::aws_with_ranges(vpc_ranges(), CidrSet::new())
+    }
+
+    /// Preset for services behind an AWS load balancer inside `vpc`, optionally
+    /// behind CloudFront edge servers inside `cloudfront`.
+    pub fn aws_with_ranges(vpc: impl Into<CidrSet>, cloudfront: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(rules(vpc.into(), cloudfront.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    const ALB: &str = "10.0.1.20";
+
+    fn behind_cloudfront() -> IpExtractor {
+        IpExtractor::aws_with_ranges(vpc_ranges(), "130.176.0.0/16".parse::<CidrSet>().unwrap())
+    }
+
+    #[test]
+    fn test_cloudfront_viewer_address() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "192.0.2.1, 198.51.100.7, 130.176.1.1".to_string(),
+        );
+        headers.insert(
+            "cloudfront-viewer-address".to_string(),
+            "198.51.100.7:46532".to_string(),
+        );
+
+        let extraction = behind_cloudfront()
+            .extract_detailed(&headers, Some(ALB.to_string()))
+            .unwrap();
+        assert_eq!(extraction.addr().to_string(), "198.51.100.7:46532");
+        assert!(extraction.anomalies.is_empty());
+
+        // Sent straight to the load balancer, the viewer header is not trusted
+        headers.insert("x-forwarded-for".to_string(), "203.0.113.9".to_string());
+        let ip = behind_cloudfront().extract(&headers, Some(ALB.to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_untrusted_peer() {
+        let mut headers = HashMap::new();
+        headers.insert("x-forwarded-for".to_string(), "198.51.100.7".to_string());
+        let ip = IpExtractor::aws().extract(&headers, Some("203.0.113.9".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse::<IpAddr>().unwrap()));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_parse_ip_ranges() {
+        let json = r#"{
+            "syncToken": "1",
+            "prefixes": [
+                {"ip_prefix": "130.176.0.0/16", "region": "GLOBAL", "service": "CLOUDFRONT"},
+                {"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "AMAZON"}
+            ],
+            "ipv6_prefixes": [
+                {"ipv6_prefix": "2600:9000::/28", "region": "GLOBAL", "service": "CLOUDFRONT"}
+            ]
+        }"#;
+        let set = parse_ip_ranges(json, "CLOUDFRONT").unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&"2600:9000::1".parse().unwrap()));
+        assert!(parse_ip_ranges("{}", "CLOUDFRONT").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+/* src/presets/azure.rs */
+
+//! Azure Front Door.
+//!
+//! Front Door's backend ranges are shared by every tenant, so its headers are
+//! only trusted when `X-Azure-FDID` names our own Front Door profile.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use std::collections::HashMap;
+//!
+//! let fdid = "a0a0a0a0-bbbb-cccc-dddd-e1e1e1e1e1e1";
+//! let mut headers = HashMap::new();
+//! headers.insert("x-azure-socketip".to_string(), "198.51.100.7".to_string());
+//! headers.insert("x-azure-fdid".to_string(), fdid.to_string());
+//!
+//! let extractor = IpExtractor::azure_front_door(fdid);
+//! assert_eq!(extractor.extract(&headers, Some("147.243.1.1".to_string())), Some("198.51.100.7".parse().unwrap()));
+//!
+//! // Another tenant's Front Door
+//! headers.insert("x-azure-fdid".to_string(), "ffffffff-0000-0000-0000-000000000000".to_string());
+//! assert_eq!(extractor.extract(&headers, Some("147.243.1.1".to_string())), Some("147.243.1.1".parse().unwrap()));
+//! ```
+
+#[cfg(feature = "json")]
+use std::io;
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use crate::cidr::CidrSet;
+#[cfg(feature = "json")]
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::rule::{Condition, HeaderRule};
+
+/// A snapshot of the `AzureFrontDoor.Backend` service tag.
+pub const FRONT_DOOR_BACKEND_RANGES: &[&str] = &["147.243.0.0/16", "2a01:111:2050::/44"];
+
+/// The service tag of the addresses Front Door connects to origins from.
+pub const FRONT_DOOR_BACKEND_TAG: &str = "AzureFrontDoor.Backend";
+
+/// The bundled Front Door backend ranges.
+pub fn front_door_backend_ranges() -> CidrSet {
+    FRONT_DOOR_BACKEND_RANGES
+        .iter()
+        .map(|range| range.parse().expect("bundled range is valid"))
+        .collect()
+}
+
+/// Collect the address prefixes of the service tag `tag` from the contents of a
+/// [Service Tags](https://www.microsoft.com/en-us/download/details.aspx?id=56519)
+/// JSON file.
+#[cfg(feature = "json")]
+pub fn parse_service_tags(json: &str, tag: &str) -> Result<CidrSet> {
+    use serde_json::Value;
+
+    let invalid = |message: String| RealIpError::InvalidRangeList(message);
+    let doc: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+    let prefixes = doc
+        .get("values")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("missing values".to_string()))?
+        .iter()
+        .find(|value| value.get("name").and_then(Value::as_str) == Some(tag))
+        .ok_or_else(|| invalid(format!("missing service tag {}", tag)))?
+        .pointer("/properties/addressPrefixes")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid(format!("{} has no addressPrefixes", tag)))?;
+
+    prefixes
+        .iter()
+        .map(|prefix| match prefix.as_str() {
+            Some(prefix) => prefix.parse(),
+            None => Err(invalid(format!("non-string prefix in {}", tag))),
+        })
+        .collect()
+}
+
+/// Load the `AzureFrontDoor.Backend` prefixes from a local copy of the Service Tags file.
+#[cfg(feature = "json")]
+pub fn load_front_door_backend_ranges(path: impl AsRef<Path>) -> io::Result<CidrSet> {
+    parse_service_tags(&std::fs::read_to_string(path)?, FRONT_DOOR_BACKEND_TAG)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The header rules of the preset, honored only from peers inside `ranges`
+/// that send `X-Azure-FDID: <fdid>`.
+///
+/// `X-Azure-SocketIP`, the address that connected to Front Door, is the
+/// client. `X-Azure-ClientIP` follows the client's own `X-Forwarded-For` and
+/// can be set by anyone, so it is only cross-checked and reported as an anomaly
+/// when it differs.
+pub fn front_door_rules(fdid: &str, ranges: CidrSet) -> Vec<HeaderRule> {
+    let fdid = Condition::HeaderEquals {
+        header: "x-azure-fdid".to_string(),
+        value: fdid.to_string(),
+    };
+    ["x-azure-socketip", "x-azure-clientip"]
+        .into_iter()
+        .map(|name| {
+            HeaderRule::single_ip(name)
+                .trusted_peers(ranges.clone())
+                .when(fdid.clone())
+        })
+        .collect()
+}
+
+impl IpExtractor {
+    /// Preset for origins behind the Azure Front Door profile `fdid`, using the
+    /// bundled backend ranges.
+    pub fn azure_front_door(fdid: &str) -> Self {
+        Self::azure_front_door_with_ranges(fdid, front_door_backend_ranges())
+    }
+
+    /// Preset for origins behind the Azure Front Door profile `fdid`, connecting from `ranges`.
+    pub fn azure_front_door_with_ranges(fdid: &str, ranges: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(front_door_rules(fdid, ranges.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extraction::Anomaly;
+    use std::collections::HashMap;
+
+    const FDID: &str = "a0a0a0a0-bbbb-cccc-dddd-e1e1e1e1e1e1";
+    const EDGE: &str = "147.243.10.1";
+
+    #[test]
+    fn test_requires_fdid_and_backend() {
+        let mut headers = HashMap::new();
+        headers.insert("x-azure-socketip".to_string(), "198.51.100.7".to_string());
+        headers.insert("x-azure-clientip".to_string(), "192.0.2.1".to_string());
+        headers.insert("x-azure-fdid".to_string(), FDID.to_uppercase());
+        let extractor = IpExtractor::azure_front_door(FDID);
+
+        let extraction = extractor
+            .extract_detailed(&headers, Some(EDGE.to_string()))
+            .unwrap();
+        assert_eq!(
+            extraction.ip,
+            "198.51.100.7".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert!(matches!(
+            extraction.anomalies[..],
+            [Anomaly::HeaderDisagreement { .. }]
+        ));
+
+        let ip = extractor.extract(&headers, Some("203.0.113.9".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+
+        headers.remove("x-azure-fdid");
+        let ip = extractor.extract(&headers, Some(EDGE.to_string()));
+        assert_eq!(ip, Some(EDGE.parse().unwrap()));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_parse_service_tags() {
+        let json = r#"{
+            "changeNumber": 1,
+            "cloud": "Public",
+            "values": [
+                {"name": "AzureCloud", "properties": {"addressPrefixes": ["13.64.0.0/11"]}},
+                {"name": "AzureFrontDoor.Backend", "id": "AzureFrontDoor.Backend",
+                 "properties": {"systemService": "AzureFrontDoor",
+                                "addressPrefixes": ["13.73.248.8/29", "2a01:111:2050::/44"]}}
+            ]
+        }"#;
+        let set = parse_service_tags(json, FRONT_DOOR_BACKEND_TAG).unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&"13.73.248.9".parse().unwrap()));
+        assert!(parse_service_tags(json, "AzureFrontDoor.Frontend").is_err());
+    }
+}
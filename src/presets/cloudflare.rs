@@ -0,0 +1,193 @@
+/* src/presets/cloudflare.rs */
+
+//! Cloudflare, which sends the client address in `CF-Connecting-IP`.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use std::collections::HashMap;
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("cf-connecting-ip".to_string(), "198.51.100.7".to_string());
+//!
+//! let extractor = IpExtractor::cloudflare();
+//! // From a Cloudflare edge server, the header is honored
+//! assert_eq!(extractor.extract(&headers, Some("173.245.48.1".to_string())), Some("198.51.100.7".parse().unwrap()));
+//! // From anyone else, it is ignored
+//! assert_eq!(extractor.extract(&headers, Some("203.0.113.9".to_string())), Some("203.0.113.9".parse().unwrap()));
+//! ```
+
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainEntry, Condition, HeaderRule};
+
+/// The header Cloudflare always sends the client address in.
+const CONNECTING_IP: &str = "cf-connecting-ip";
+
+/// Cloudflare's IPv4 ranges, from <https://www.cloudflare.com/ips-v4>.
+pub const IPV4_RANGES: &[&str] = &[
+    "173.245.48.0/20",
+    "103.21.244.0/22",
+    "103.22.200.0/22",
+    "103.31.4.0/22",
+    "141.101.64.0/18",
+    "108.162.192.0/18",
+    "190.93.240.0/20",
+    "188.114.96.0/20",
+    "197.234.240.0/22",
+    "198.41.128.0/17",
+    "162.158.0.0/15",
+    "104.16.0.0/13",
+    "104.24.0.0/14",
+    "172.64.0.0/13",
+    "131.0.72.0/22",
+];
+
+/// Cloudflare's IPv6 ranges, from <https://www.cloudflare.com/ips-v6>.
+pub const IPV6_RANGES: &[&str] = &[
+    "2400:cb00::/32",
+    "2606:4700::/32",
+    "2803:f800::/32",
+    "2405:b500::/32",
+    "2405:8100::/32",
+    "2a06:98c0::/29",
+    "2c0f:f248::/32",
+];
+
+/// The bundled Cloudflare ranges.
+pub fn ranges() -> CidrSet {
+    IPV4_RANGES
+        .iter()
+        .chain(IPV6_RANGES)
+        .map(|range| range.parse().expect("bundled range is valid"))
+        .collect()
+}
+
+/// Load Cloudflare ranges from local copies of the published lists, one range per line.
+pub fn load_ranges<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> io::Result<CidrSet> {
+    let mut set = CidrSet::new();
+    for path in paths {
+        set.extend(CidrSet::from_file(path)?.iter().copied());
+    }
+    Ok(set)
+}
+
+/// Where Cloudflare draws pseudo IPv4 addresses from.
+const PSEUDO_IPV4_RANGE: &str = "240.0.0.0/4";
+
+/// The header rules of the preset, honored only from peers inside `ranges`.
+///
+/// With Pseudo IPv4 set to overwrite headers, IPv6 clients get a pseudo address
+/// from `240.0.0.0/4` in `CF-Connecting-IP` and their real address in
+/// `CF-Connecting-IPv6`, which is only read then. A pseudo address without a
+/// real one fails the extraction rather than returning the edge server. With
+/// Pseudo IPv4 set to add a header, `CF-Connecting-IP` keeps the real address
+/// and `Cf-Pseudo-IPv4` is ignored.
+pub fn rules(ranges: CidrSet) -> Vec<HeaderRule> {
+    let pseudo = Condition::LastHopIn {
+        header: CONNECTING_IP.to_string(),
+        ranges: PSEUDO_IPV4_RANGE.parse().expect("bundled range is valid"),
+    };
+    vec![
+        HeaderRule::single_ip("cf-connecting-ipv6")
+            .trusted_peers(ranges.clone())
+            .when(pseudo),
+        HeaderRule::custom(CONNECTING_IP, parse_connecting_ip)
+            .trusted_peers(ranges)
+            .authoritative(),
+    ]
+}
+
+/// Parse `CF-Connecting-IP`, hiding pseudo IPv4 addresses.
+fn parse_connecting_ip(value: &str) -> Vec<ChainEntry> {
+    vec![match value.parse::<ClientAddr>() {
+        Ok(addr) if is_pseudo_ipv4(&addr.ip) => ChainEntry::Opaque(value.trim().to_string()),
+        Ok(addr) => ChainEntry::Addr(addr),
+        Err(_) => ChainEntry::Invalid(value.trim().to_string()),
+    }]
+}
+
+/// Cloudflare draws pseudo IPv4 addresses from the reserved class E range.
+fn is_pseudo_ipv4(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V4(v4) if v4.octets()[0] >= 240)
+}
+
+impl IpExtractor {
+    /// Preset for origins behind Cloudflare, using the bundled ranges.
+    ///
+    /// `CF-Connecting-IPv6` and `CF-Connecting-IP` are only honored when the
+    /// socket peer is a Cloudflare edge server; see [`rules`] for Pseudo IPv4.
+    pub fn cloudflare() -> Self {
+        Self::cloudflare_with_ranges(ranges())
+    }
+
+    /// Preset for origins behind Cloudflare, trusting `ranges` as the edge servers.
+    pub fn cloudflare_with_ranges(ranges: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(rules(ranges.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const EDGE: &str = "2400:cb00::1";
+
+    #[test]
+    fn test_only_trusts_edge() {
+        let mut headers = HashMap::new();
+        headers.insert("cf-connecting-ip".to_string(), "198.51.100.7".to_string());
+        let extractor = IpExtractor::cloudflare();
+
+        let extraction = extractor
+            .extract_detailed(&headers, Some(EDGE.to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            extractor.extract(&headers, Some("[2001:db8::1]:443".to_string())),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_pseudo_ipv4() {
+        let mut headers = HashMap::new();
+        headers.insert("cf-connecting-ip".to_string(), "240.16.0.1".to_string());
+        headers.insert("cf-connecting-ipv6".to_string(), "2001:db8::7".to_string());
+        headers.insert("cf-pseudo-ipv4".to_string(), "240.16.0.1".to_string());
+
+        let extraction = IpExtractor::cloudflare()
+            .extract_detailed(&headers, Some(EDGE.to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip, "2001:db8::7".parse::<IpAddr>().unwrap());
+        assert!(extraction.anomalies.is_empty());
+
+        // Without the IPv6 header, neither the pseudo address nor the edge is returned
+        headers.remove("cf-connecting-ipv6");
+        let extractor = IpExtractor::cloudflare();
+        assert_eq!(extractor.extract(&headers, Some(EDGE.to_string())), None);
+        assert_eq!(
+            extractor.try_extract(&headers, Some(EDGE.to_string())),
+            Err(crate::RealIpError::NoValidIp)
+        );
+    }
+
+    #[test]
+    fn test_ipv6_header_needs_pseudo_ipv4() {
+        let mut headers = HashMap::new();
+        headers.insert("cf-connecting-ip".to_string(), "198.51.100.7".to_string());
+        headers.insert("cf-connecting-ipv6".to_string(), "2001:db8::7".to_string());
+
+        // Without Pseudo IPv4, the client may have sent CF-Connecting-IPv6 itself
+        let extraction = IpExtractor::cloudflare()
+            .extract_detailed(&headers, Some(EDGE.to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert!(extraction.anomalies.is_empty());
+    }
+}
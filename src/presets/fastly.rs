@@ -0,0 +1,140 @@
+/* src/presets/fastly.rs */
+
+//! Fastly, which sends the client address in `Fastly-Client-IP`.
+//!
+//! Fastly keeps a `Fastly-Client-IP` sent by the client unless the service
+//! resets it, so set `req.http.Fastly-Client-IP = client.ip` in `vcl_recv` on
+//! the edge.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use std::collections::HashMap;
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("fastly-client-ip".to_string(), "198.51.100.7".to_string());
+//!
+//! let ip = IpExtractor::fastly().extract(&headers, Some("151.101.1.1".to_string()));
+//! assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+//! ```
+
+#[cfg(feature = "json")]
+use std::io;
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use crate::cidr::CidrSet;
+#[cfg(feature = "json")]
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::rule::HeaderRule;
+
+/// A snapshot of Fastly's ranges, from <https://api.fastly.com/public-ip-list>.
+pub const RANGES: &[&str] = &[
+    "23.235.32.0/20",
+    "43.249.72.0/22",
+    "103.244.50.0/24",
+    "103.245.222.0/23",
+    "103.245.224.0/24",
+    "104.156.80.0/20",
+    "140.248.64.0/18",
+    "140.248.128.0/17",
+    "146.75.0.0/17",
+    "151.101.0.0/16",
+    "157.52.64.0/18",
+    "167.82.0.0/17",
+    "167.82.128.0/20",
+    "167.82.160.0/20",
+    "167.82.224.0/20",
+    "172.111.64.0/18",
+    "185.31.16.0/22",
+    "199.27.72.0/21",
+    "199.232.0.0/16",
+    "2a04:4e40::/32",
+    "2a04:4e42::/32",
+];
+
+/// The bundled Fastly ranges.
+pub fn ranges() -> CidrSet {
+    RANGES
+        .iter()
+        .map(|range| range.parse().expect("bundled range is valid"))
+        .collect()
+}
+
+/// Collect the ranges from the contents of Fastly's `public-ip-list` response.
+#[cfg(feature = "json")]
+pub fn parse_public_ip_list(json: &str) -> Result<CidrSet> {
+    use serde_json::Value;
+
+    let invalid = |message: String| RealIpError::InvalidRangeList(message);
+    let doc: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+
+    let mut set = CidrSet::new();
+    for list in ["addresses", "ipv6_addresses"] {
+        let Some(entries) = doc.get(list) else {
+            continue;
+        };
+        for entry in entries
+            .as_array()
+            .ok_or_else(|| invalid(format!("{} is not a list", list)))?
+        {
+            let range = entry
+                .as_str()
+                .ok_or_else(|| invalid(format!("non-string range in {}", list)))?;
+            set.insert(range.parse()?);
+        }
+    }
+    if set.is_empty() {
+        return Err(invalid("no addresses".to_string()));
+    }
+    Ok(set)
+}
+
+/// Load the ranges from a local copy of Fastly's `public-ip-list` response.
+#[cfg(feature = "json")]
+pub fn load_public_ip_list(path: impl AsRef<Path>) -> io::Result<CidrSet> {
+    parse_public_ip_list(&std::fs::read_to_string(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The header rules of the preset, honored only from peers inside `ranges`.
+pub fn rules(ranges: CidrSet) -> Vec<HeaderRule> {
+    vec![HeaderRule::single_ip("fastly-client-ip").trusted_peers(ranges)]
+}
+
+impl IpExtractor {
+    /// Preset for origins behind Fastly, using the bundled ranges.
+    pub fn fastly() -> Self {
+        Self::fastly_with_ranges(ranges())
+    }
+
+    /// Preset for origins behind Fastly, trusting `ranges` as its servers.
+    pub fn fastly_with_ranges(ranges: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(rules(ranges.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_only_trusts_fastly() {
+        let mut headers = HashMap::new();
+        headers.insert("fastly-client-ip".to_string(), "198.51.100.7".to_string());
+
+        let ip = IpExtractor::fastly().extract(&headers, Some("203.0.113.9".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+        let ip = IpExtractor::fastly().extract(&headers, Some("[2a04:4e42::1]:443".to_string()));
+        assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_parse_public_ip_list() {
+        let json = r#"{"addresses": ["151.101.0.0/16"], "ipv6_addresses": ["2a04:4e42::/32"]}"#;
+        assert_eq!(parse_public_ip_list(json).unwrap().len(), 2);
+        assert!(parse_public_ip_list("{}").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+/* src/presets/gcp.rs */
+
+//! Google Cloud external Application Load Balancers.
+//!
+//! The load balancer appends `<client-ip>,<load-balancer-ip>` to
+//! `X-Forwarded-For`, so the client is the second entry from the right, and
+//! connects to the backend from its documented proxy ranges.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use std::collections::HashMap;
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "192.0.2.1, 198.51.100.7, 34.120.0.1".to_string());
+//!
+//! let ip = IpExtractor::gcp().extract(&headers, Some("35.191.0.10".to_string()));
+//! assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+//! ```
+
+#[cfg(feature = "json")]
+use std::io;
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use crate::cidr::CidrSet;
+#[cfg(feature = "json")]
+use crate::error::{RealIpError, Result};
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainSelection, HeaderRule};
+
+/// The ranges Google Front Ends connect to backends from, as documented for
+/// load balancer and health check firewall rules.
+pub const LB_RANGES: &[&str] = &["130.211.0.0/22", "35.191.0.0/16"];
+
+/// The bundled load balancer ranges.
+pub fn lb_ranges() -> CidrSet {
+    LB_RANGES
+        .iter()
+        .map(|range| range.parse().expect("bundled range is valid"))
+        .collect()
+}
+
+/// Collect the prefixes of a Google range file, such as
+/// [`cloud.json`](https://www.gstatic.com/ipranges/cloud.json) or `goog.json`.
+///
+/// These files list every address Google Cloud customers may use, not the
+/// load balancer source ranges. Do not pass them to
+/// [`IpExtractor::gcp_with_ranges`]: any GCP tenant could then forge the client.
+#[cfg(feature = "json")]
+pub fn parse_ip_ranges(json: &str) -> Result<CidrSet> {
+    use serde_json::Value;
+
+    let invalid = |message: String| RealIpError::InvalidRangeList(message);
+    let doc: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+    let entries = doc
+        .get("prefixes")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("missing prefixes".to_string()))?;
+
+    let mut set = CidrSet::new();
+    for entry in entries {
+        let prefix = entry
+            .get("ipv4Prefix")
+            .or_else(|| entry.get("ipv6Prefix"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("entry without ipv4Prefix or ipv6Prefix".to_string()))?;
+        set.insert(prefix.parse()?);
+    }
+    Ok(set)
+}
+
+/// Load the prefixes of a local copy of a Google range file. See
+/// [`parse_ip_ranges`] for why they are not load balancer ranges.
+#[cfg(feature = "json")]
+pub fn load_ip_ranges(path: impl AsRef<Path>) -> io::Result<CidrSet> {
+    parse_ip_ranges(&std::fs::read_to_string(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The header rules of the preset: the second `X-Forwarded-For` entry from the
+/// right, honored only from peers inside `ranges`.
+pub fn rules(ranges: CidrSet) -> Vec<HeaderRule> {
+    vec![
+        HeaderRule::comma_list("x-forwarded-for")
+            .trusted_peers(ranges)
+            .selection(ChainSelection::TrustedHops(2)),
+    ]
+}
+
+impl IpExtractor {
+    /// Preset for services behind a Google Cloud external Application Load
+    /// Balancer, using the bundled proxy ranges.
+    ///
+    /// A chain with fewer than two entries from the load balancer fails the
+    /// extraction, as with [`trusted_hops`](Self::trusted_hops).
+    pub fn gcp() -> Self {
+        Self::gcp_with_ranges(lb_ranges())
+    }
+
+    /// Preset for services behind a Google Cloud load balancer connecting from `ranges`.
+    ///
+    /// `ranges` must only hold the load balancer's source ranges, never the
+    /// customer-wide lists read by `load_ip_ranges`.
+    pub fn gcp_with_ranges(ranges: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(rules(ranges.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RealIpError;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_second_from_right() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 34.120.0.1".to_string(),
+        );
+        let extractor = IpExtractor::gcp();
+
+        let extraction = extractor
+            .extract_detailed(&headers, Some("130.211.1.5".to_string()))
+            .unwrap();
+        assert_eq!(
+            extraction.ip,
+            "198.51.100.7".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(extraction.index, Some(0));
+
+        // Not from the load balancer: the header is ignored
+        let ip = extractor.extract(&headers, Some("203.0.113.9".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+
+        headers.insert("x-forwarded-for".to_string(), "34.120.0.1".to_string());
+        assert!(matches!(
+            extractor.try_extract(&headers, Some("130.211.1.5".to_string())),
+            Err(RealIpError::ChainTooShort {
+                len: 1,
+                hops: 2,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_parse_ip_ranges() {
+        let json = r#"{
+            "syncToken": "1",
+            "creationTime": "2024-01-01T00:00:00",
+            "prefixes": [
+                {"ipv4Prefix": "34.1.208.0/20", "service": "Google Cloud", "scope": "africa-south1"},
+                {"ipv6Prefix": "2600:1900:8000::/44", "service": "Google Cloud", "scope": "us-east1"}
+            ]
+        }"#;
+        let set = parse_ip_ranges(json).unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&"34.1.210.1".parse().unwrap()));
+        assert!(parse_ip_ranges(r#"{"prefixes": [{}]}"#).is_err());
+    }
+}
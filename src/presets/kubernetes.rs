@@ -0,0 +1,146 @@
+/* src/presets/kubernetes.rs */
+
+//! Kubernetes ingress controllers and service meshes.
+//!
+//! Requests usually pass through a cloud load balancer, an ingress controller
+//! and possibly an Envoy sidecar before reaching the pod. Each preset takes the
+//! ranges to trust as proxies: the cluster's pod and service CIDRs, plus the
+//! load balancer's addresses when it connects from outside them.
+//!
+//! ```rust
+//! use real::{CidrSet, IpExtractor};
+//! use std::collections::HashMap;
+//!
+//! let cluster: CidrSet = "10.244.0.0/16, 10.96.0.0/12".parse().unwrap();
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "192.0.2.1, 198.51.100.7, 10.244.1.5".to_string());
+//!
+//! // Traefik appends its peer, here a node in the pod network
+//! let ip = IpExtractor::traefik(cluster).extract(&headers, Some("10.244.2.9".to_string()));
+//! assert_eq!(ip, Some("198.51.100.7".parse().unwrap()));
+//! ```
+
+use crate::cidr::{Cidr, CidrSet};
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainSelection, HeaderRule};
+
+/// Loopback ranges, where an Istio sidecar connects to the app from.
+pub const SIDECAR_RANGES: &[&str] = &["127.0.0.0/8", "::1/128"];
+
+/// The header rules for ingress-nginx with `use-forwarded-headers` enabled.
+///
+/// The controller sets `X-Real-IP` to the client it computed from its
+/// `proxy-real-ip-cidr`. With `compute-full-forwarded-for` it also appends its
+/// peer to `X-Forwarded-For`, which is walked past `trusted` and cross-checked.
+pub fn ingress_nginx_rules(trusted: CidrSet) -> Vec<HeaderRule> {
+    vec![
+        HeaderRule::single_ip("x-real-ip").trusted_peers(trusted.clone()),
+        HeaderRule::comma_list("x-forwarded-for")
+            .trusted_peers(trusted.clone())
+            .selection(ChainSelection::RightmostUntrusted(trusted)),
+    ]
+}
+
+/// The header rules for Traefik with `forwardedHeaders.trustedIPs` set.
+///
+/// Traefik keeps `X-Forwarded-For` from its trusted IPs and appends its peer.
+/// Its `X-Real-Ip` is always that peer, even behind a load balancer, so it is
+/// not read.
+pub fn traefik_rules(trusted: CidrSet) -> Vec<HeaderRule> {
+    vec![
+        HeaderRule::comma_list("x-forwarded-for")
+            .trusted_peers(trusted.clone())
+            .selection(ChainSelection::RightmostUntrusted(trusted)),
+    ]
+}
+
+/// The header rules for Istio with `numTrustedProxies` proxies in front of the
+/// ingress gateway.
+///
+/// The gateway sets `x-envoy-external-address` to the client and appends its
+/// peer to `X-Forwarded-For`, so the chain is cross-checked one hop further
+/// than `numTrustedProxies`. The sidecar's loopback address is trusted along
+/// with `trusted`.
+pub fn istio_rules(num_trusted_proxies: usize, trusted: CidrSet) -> Vec<HeaderRule> {
+    let peers: CidrSet = trusted.iter().copied().chain(sidecar_ranges()).collect();
+    vec![
+        HeaderRule::single_ip("x-envoy-external-address").trusted_peers(peers.clone()),
+        HeaderRule::comma_list("x-forwarded-for")
+            .trusted_peers(peers)
+            .selection(ChainSelection::TrustedHops(num_trusted_proxies + 1)),
+    ]
+}
+
+fn sidecar_ranges() -> impl Iterator<Item = Cidr> {
+    SIDECAR_RANGES
+        .iter()
+        .map(|range| range.parse().expect("bundled range is valid"))
+}
+
+impl IpExtractor {
+    /// Preset for pods behind ingress-nginx, trusting `trusted` as proxies.
+    pub fn ingress_nginx(trusted: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(ingress_nginx_rules(trusted.into()))
+    }
+
+    /// Preset for pods behind Traefik, trusting `trusted` as proxies.
+    pub fn traefik(trusted: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(traefik_rules(trusted.into()))
+    }
+
+    /// Preset for pods in an Istio mesh whose gateway is configured with
+    /// `numTrustedProxies`, trusting `trusted` as proxies.
+    pub fn istio(num_trusted_proxies: usize, trusted: impl Into<CidrSet>) -> Self {
+        Self::new().with_rules(istio_rules(num_trusted_proxies, trusted.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn cluster() -> CidrSet {
+        "10.244.0.0/16, 10.96.0.0/12".parse().unwrap()
+    }
+
+    #[test]
+    fn test_ingress_nginx() {
+        let mut headers = HashMap::new();
+        headers.insert("x-real-ip".to_string(), "198.51.100.7".to_string());
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "198.51.100.7, 10.244.0.1".to_string(),
+        );
+        let extractor = IpExtractor::ingress_nginx(cluster());
+
+        let extraction = extractor
+            .extract_detailed(&headers, Some("10.244.3.4".to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip.to_string(), "198.51.100.7");
+        assert!(extraction.anomalies.is_empty());
+
+        // Straight to the pod from outside the cluster
+        let ip = extractor.extract(&headers, Some("203.0.113.9".to_string()));
+        assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_istio_sidecar() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-envoy-external-address".to_string(),
+            "198.51.100.7".to_string(),
+        );
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "192.0.2.1, 198.51.100.7, 10.0.0.5".to_string(),
+        );
+
+        let extraction = IpExtractor::istio(1, cluster())
+            .extract_detailed(&headers, Some("127.0.0.6".to_string()))
+            .unwrap();
+        assert_eq!(extraction.ip.to_string(), "198.51.100.7");
+        assert!(extraction.anomalies.is_empty());
+    }
+}
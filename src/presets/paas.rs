@@ -0,0 +1,200 @@
+/* src/presets/paas.rs */
+
+//! Hosting platforms that put their own router in front of every app.
+//!
+//! The router's addresses are internal to the platform and not published, so
+//! these presets trust the headers from any peer. They are only safe when the
+//! app cannot be reached except through the router, which is the default on
+//! each platform.
+//!
+//! ```rust
+//! use real::IpExtractor;
+//! use real::presets::paas::Platform;
+//!
+//! let vars = [("FLY_APP_NAME", "my-app")];
+//! let platform = Platform::detect_from(|name| {
+//!     vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+//! });
+//! assert_eq!(platform, Some(Platform::Fly));
+//!
+//! // In a deployed app
+//! let extractor = IpExtractor::auto_detect().unwrap_or_default();
+//! ```
+
+use std::fmt;
+
+use crate::extractor::IpExtractor;
+use crate::rule::{ChainSelection, HeaderRule};
+
+/// A hosting platform with a preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// Fly.io, whose proxy sets `Fly-Client-IP`.
+    Fly,
+    /// Heroku, whose router appends the connecting address to `X-Forwarded-For`.
+    Heroku,
+    /// Render, which sets `True-Client-IP`.
+    Render,
+    /// Railway, whose edge sets `X-Real-IP`.
+    Railway,
+    /// Vercel, which sets `X-Vercel-Forwarded-For`.
+    Vercel,
+    /// Netlify, which sets `X-Nf-Client-Connection-IP`.
+    Netlify,
+}
+
+impl Platform {
+    /// Every platform, in detection order.
+    pub const ALL: [Platform; 6] = [
+        Self::Fly,
+        Self::Heroku,
+        Self::Render,
+        Self::Railway,
+        Self::Vercel,
+        Self::Netlify,
+    ];
+
+    /// The environment variable the platform sets in every app.
+    pub fn env_var(self) -> &'static str {
+        match self {
+            Self::Fly => "FLY_APP_NAME",
+            Self::Heroku => "DYNO",
+            Self::Render => "RENDER",
+            Self::Railway => "RAILWAY_PROJECT_ID",
+            Self::Vercel => "VERCEL",
+            Self::Netlify => "NETLIFY",
+        }
+    }
+
+    /// Detect the platform from the process environment.
+    pub fn detect() -> Option<Self> {
+        Self::detect_from(|name| std::env::var(name).ok())
+    }
+
+    /// Detect the platform from environment variables looked up with `var`.
+    /// Empty variables are ignored.
+    pub fn detect_from(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|platform| var(platform.env_var()).is_some_and(|value| !value.is_empty()))
+    }
+
+    /// The header rules of the platform.
+    pub fn rules(self) -> Vec<HeaderRule> {
+        match self {
+            Self::Fly => vec![HeaderRule::single_ip("fly-client-ip")],
+            // The router appends the address it saw; anything to the left came
+            // from the client.
+            Self::Heroku => {
+                vec![HeaderRule::comma_list("x-forwarded-for").selection(ChainSelection::Rightmost)]
+            }
+            Self::Render => vec![HeaderRule::single_ip("true-client-ip")],
+            Self::Railway => vec![HeaderRule::single_ip("x-real-ip")],
+            Self::Vercel => vec![HeaderRule::single_ip("x-vercel-forwarded-for")],
+            Self::Netlify => vec![HeaderRule::single_ip("x-nf-client-connection-ip")],
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fly => "Fly.io",
+            Self::Heroku => "Heroku",
+            Self::Render => "Render",
+            Self::Railway => "Railway",
+            Self::Vercel => "Vercel",
+            Self::Netlify => "Netlify",
+        })
+    }
+}
+
+impl IpExtractor {
+    /// Preset for apps hosted on `platform`.
+    pub fn for_platform(platform: Platform) -> Self {
+        Self::new().with_rules(platform.rules())
+    }
+
+    /// Preset for the platform the process runs on, detected from its
+    /// environment variables, or `None` outside a known platform.
+    pub fn auto_detect() -> Option<Self> {
+        Platform::detect().map(Self::for_platform)
+    }
+
+    /// Preset for apps on Fly.io.
+    pub fn fly() -> Self {
+        Self::for_platform(Platform::Fly)
+    }
+
+    /// Preset for apps on Heroku.
+    pub fn heroku() -> Self {
+        Self::for_platform(Platform::Heroku)
+    }
+
+    /// Preset for apps on Render.
+    pub fn render() -> Self {
+        Self::for_platform(Platform::Render)
+    }
+
+    /// Preset for apps on Railway.
+    pub fn railway() -> Self {
+        Self::for_platform(Platform::Railway)
+    }
+
+    /// Preset for apps on Vercel.
+    pub fn vercel() -> Self {
+        Self::for_platform(Platform::Vercel)
+    }
+
+    /// Preset for apps on Netlify.
+    pub fn netlify() -> Self {
+        Self::for_platform(Platform::Netlify)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const PEER: &str = "10.1.2.3";
+
+    #[test]
+    fn test_platform_headers() {
+        let cases = [
+            (IpExtractor::fly(), "fly-client-ip", "198.51.100.7"),
+            (
+                IpExtractor::heroku(),
+                "x-forwarded-for",
+                "192.0.2.1, 198.51.100.7",
+            ),
+            (IpExtractor::render(), "true-client-ip", "198.51.100.7"),
+            (IpExtractor::railway(), "x-real-ip", "198.51.100.7"),
+            (
+                IpExtractor::vercel(),
+                "x-vercel-forwarded-for",
+                "198.51.100.7",
+            ),
+            (
+                IpExtractor::netlify(),
+                "x-nf-client-connection-ip",
+                "198.51.100.7",
+            ),
+        ];
+        for (extractor, header, value) in cases {
+            let mut headers = HashMap::new();
+            headers.insert(header.to_string(), value.to_string());
+            headers.insert("cf-connecting-ip".to_string(), "203.0.113.9".to_string());
+            let ip = extractor.extract(&headers, Some(PEER.to_string()));
+            assert_eq!(ip, Some("198.51.100.7".parse().unwrap()), "{}", header);
+        }
+    }
+
+    #[test]
+    fn test_detect_from() {
+        let env: HashMap<&str, &str> = [("DYNO", "web.1"), ("VERCEL", "")].into();
+        let var = |name: &str| env.get(name).map(|value| value.to_string());
+        assert_eq!(Platform::detect_from(var), Some(Platform::Heroku));
+        assert_eq!(Platform::detect_from(|_| None), None);
+    }
+}
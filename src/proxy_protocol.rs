@@ -0,0 +1,315 @@
+/* src/proxy_protocol.rs */
+
+//! Parser for the HAProxy PROXY protocol, versions 1 and 2.
+//!
+//! TCP load balancers such as HAProxy and AWS NLB send the client address in a
+//! header at the start of the connection instead of in HTTP headers.
+//!
+//! ```rust
+//! use real::proxy_protocol::{self, Command, Version};
+//!
+//! let buf = b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 443\r\nGET / HTTP/1.1\r\n";
+//! let (header, len) = proxy_protocol::parse(buf).unwrap().unwrap();
+//! assert_eq!(header.version, Version::V1);
+//! assert_eq!(header.command, Command::Proxy);
+//! assert_eq!(header.source, Some("203.0.113.9:51234".parse().unwrap()));
+//! assert_eq!(&buf[len..], b"GET / HTTP/1.1\r\n");
+//! ```
+//!
+//! [`ProxyHeader::to_bytes`] encodes headers for services that relay
+//! connections themselves.
+//!
+//! With the `tokio` feature, [`ProxyProtocolListener`] strips the header from
+//! accepted connections and, with the `axum` feature, exposes the addresses as
+//! `ConnectInfo<ProxiedAddr>`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::error::{RealIpError, Result};
+
+mod encode;
+#[cfg(feature = "tokio")]
+mod listener;
+mod tlv;
+
+#[cfg(feature = "tokio")]
+pub use listener::{ProxiedAddr, ProxiedStream, ProxyProtocolListener};
+pub use tlv::{ProxyTlvs, SslInfo};
+
+/// The signature that starts every version 2 header.
+pub const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a version 1 header, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Length of the fixed part of a version 2 header.
+const V2_HEADER_LEN: usize = 16;
+
+/// The protocol version of a PROXY header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The human-readable text format.
+    V1,
+    /// The binary format.
+    V2,
+}
+
+/// What the connection carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// A connection opened by the proxy itself, such as a health check. The
+    /// addresses must be ignored.
+    Local,
+    /// A connection relayed on behalf of a client.
+    Proxy,
+}
+
+/// The transport protocol of the relayed connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Unknown or unspecified, as with v1 `UNKNOWN`.
+    Unknown,
+    /// TCP or another stream protocol.
+    Stream,
+    /// UDP or another datagram protocol.
+    Datagram,
+}
+
+/// A parsed PROXY header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The protocol version.
+    pub version: Version,
+    /// The command. Always [`Command::Proxy`] for version 1.
+    pub command: Command,
+    /// The transport protocol.
+    pub transport: Transport,
+    /// The client address. `None` for `LOCAL`, `UNKNOWN` and Unix socket headers.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to. `None` whenever `source` is.
+    pub destination: Option<SocketAddr>,
+    /// The TLVs of a version 2 header. Empty for version 1.
+    pub tlvs: ProxyTlvs,
+}
+
+/// Parse a PROXY header of either version at the start of `buf`.
+///
+/// Returns the header and its length in bytes, or `Ok(None)` when `buf` holds
+/// the start of a header and more bytes must be read.
+pub fn parse(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'P') => parse_v1(buf),
+        Some(b'\r') => parse_v2(buf),
+        Some(_) => Err(invalid("missing PROXY header")),
+    }
+}
+
+/// Parse a version 1 header, such as `PROXY TCP4 203.0.113.9 192.0.2.1 51234 443\r\n`.
+pub fn parse_v1(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    if !b"PROXY ".starts_with(&buf[..buf.len().min(6)]) {
+        return Err(invalid("bad v1 signature"));
+    }
+    let Some(end) = buf.iter().take(V1_MAX_LEN).position(|&b| b == b'\n') else {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        return Ok(None);
+    };
+    let line = buf[..end]
+        .strip_suffix(b"\r")
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or_else(|| invalid("v1 header must end with CRLF"))?;
+
+    let mut fields = line.split(' ').skip(1);
+    let (source, destination, transport) = match fields.next() {
+        // The rest of the line is to be ignored
+        Some("UNKNOWN") => (None, None, Transport::Unknown),
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let fields: Vec<&str> = fields.collect();
+            let [src_ip, dst_ip, src_port, dst_port] = fields[..] else {
+                return Err(invalid("v1 header needs four address fields"));
+            };
+            let ip = |s: &str| match (family, s.parse::<IpAddr>()) {
+                ("TCP4", Ok(ip @ IpAddr::V4(_))) | ("TCP6", Ok(ip @ IpAddr::V6(_))) => Ok(ip),
+                _ => Err(invalid("v1 address does not match its family")),
+            };
+            let port = |s: &str| s.parse::<u16>().map_err(|_| invalid("invalid v1 port"));
+            (
+                Some(SocketAddr::new(ip(src_ip)?, port(src_port)?)),
+                Some(SocketAddr::new(ip(dst_ip)?, port(dst_port)?)),
+                Transport::Stream,
+            )
+        }
+        _ => return Err(invalid("unknown v1 protocol")),
+    };
+
+    let header = ProxyHeader {
+        version: Version::V1,
+        command: Command::Proxy,
+        transport,
+        source,
+        destination,
+        tlvs: ProxyTlvs::default(),
+    };
+    Ok(Some((header, end + 1)))
+}
+
+/// Parse a binary version 2 header.
+pub fn parse_v2(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    let n = buf.len().min(V2_SIGNATURE.len());
+    if buf[..n] != V2_SIGNATURE[..n] {
+        return Err(invalid("bad v2 signature"));
+    }
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(None);
+    }
+
+    if buf[12] >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+    let command = match buf[12] & 0x0f {
+        0 => Command::Local,
+        1 => Command::Proxy,
+        _ => return Err(invalid("unknown v2 command")),
+    };
+    let transport = match buf[13] & 0x0f {
+        0 => Transport::Unknown,
+        1 => Transport::Stream,
+        2 => Transport::Datagram,
+        _ => return Err(invalid("unknown v2 transport")),
+    };
+    let len = V2_HEADER_LEN + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    let body = &buf[V2_HEADER_LEN..len];
+    let (addrs, addr_len) = match buf[13] >> 4 {
+        0 => (None, 0),
+        1 => {
+            let body = body
+                .get(..12)
+                .ok_or_else(|| invalid("short v2 IPv4 block"))?;
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    body[at],
+                    body[at + 1],
+                    body[at + 2],
+                    body[at + 3],
+                ))
+            };
+            let addrs = (
+                SocketAddr::new(ip(0), be_u16(&body[8..])),
+                SocketAddr::new(ip(4), be_u16(&body[10..])),
+            );
+            (Some(addrs), 12)
+        }
+        2 => {
+            let body = body
+                .get(..36)
+                .ok_or_else(|| invalid("short v2 IPv6 block"))?;
+            let ip = |at: usize| {
+                let octets: [u8; 16] = body[at..at + 16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let addrs = (
+                SocketAddr::new(ip(0), be_u16(&body[32..])),
+                SocketAddr::new(ip(16), be_u16(&body[34..])),
+            );
+            (Some(addrs), 36)
+        }
+        3 => {
+            if body.len() < 216 {
+                return Err(invalid("short v2 Unix block"));
+            }
+            (None, 216)
+        }
+        _ => return Err(invalid("unknown v2 address family")),
+    };
+
+    // Receivers must ignore the addresses of LOCAL connections
+    let (source, destination) = match (command, addrs) {
+        (Command::Proxy, Some((source, destination))) => (Some(source), Some(destination)),
+        _ => (None, None),
+    };
+    let header = ProxyHeader {
+        version: Version::V2,
+        command,
+        transport,
+        source,
+        destination,
+        tlvs: tlv::parse(&buf[..len], V2_HEADER_LEN + addr_len)?,
+    };
+    Ok(Some((header, len)))
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn invalid(reason: &str) -> RealIpError {
+    RealIpError::InvalidProxyHeader(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        let (header, len) = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\nx")
+            .unwrap()
+            .unwrap();
+        assert_eq!(len, 45);
+        assert_eq!(header.source, Some("[2001:db8::1]:4711".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("[2001:db8::2]:443".parse().unwrap())
+        );
+
+        let (header, _) = parse(b"PROXY UNKNOWN ignored\r\n").unwrap().unwrap();
+        assert_eq!(header.transport, Transport::Unknown);
+        assert_eq!(header.source, None);
+
+        assert_eq!(parse(b"PROXY TCP4 203.0").unwrap(), None);
+        assert!(parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 192.0.2.9 192.0.2.1 1\r\n").is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(parse(&[b'P'; 200]).is_err());
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        buf.extend_from_slice(&[203, 0, 113, 9, 192, 0, 2, 1]);
+        buf.extend_from_slice(&51234u16.to_be_bytes());
+        buf.extend_from_slice(&443u16.to_be_bytes());
+        buf.extend_from_slice(b"GET");
+
+        assert_eq!(parse(&buf[..20]).unwrap(), None);
+        let (header, len) = parse(&buf).unwrap().unwrap();
+        assert_eq!(len, 28);
+        assert_eq!(header.version, Version::V2);
+        assert_eq!(header.transport, Transport::Stream);
+        assert_eq!(header.source, Some("203.0.113.9:51234".parse().unwrap()));
+        assert_eq!(header.destination, Some("192.0.2.1:443".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_v2_local_and_errors() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x20, 0x11, 0, 12]);
+        buf.extend_from_slice(&[0; 12]);
+        let (header, _) = parse(&buf).unwrap().unwrap();
+        assert_eq!(header.command, Command::Local);
+        assert_eq!(header.source, None);
+
+        buf[12] = 0x11;
+        assert!(parse(&buf).is_err());
+        buf[12] = 0x21;
+        buf[15] = 4;
+        assert!(parse(&buf).is_err());
+    }
+}
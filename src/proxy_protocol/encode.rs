@@ -0,0 +1,260 @@
+/* src/proxy_protocol/encode.rs */
+
+use std::net::{IpAddr, SocketAddr};
+
+use super::tlv::{self, ProxyTlvs};
+use super::{Command, ProxyHeader, Transport, V2_HEADER_LEN, V2_SIGNATURE, Version, invalid};
+use crate::addr::ClientAddr;
+use crate::error::Result;
+
+impl ProxyHeader {
+    /// A version 2 header relaying a TCP connection from `source` to `destination`.
+    pub fn new(source: SocketAddr, destination: SocketAddr) -> Self {
+        Self {
+            version: Version::V2,
+            command: Command::Proxy,
+            transport: Transport::Stream,
+            source: Some(source),
+            destination: Some(destination),
+            tlvs: ProxyTlvs::default(),
+        }
+    }
+
+    /// A header relaying a client found by [`IpExtractor`](crate::IpExtractor).
+    /// The source port is zero when the client port is unknown.
+    pub fn for_client(client: ClientAddr, destination: SocketAddr) -> Self {
+        Self::new(
+            SocketAddr::new(client.ip, client.port.unwrap_or(0)),
+            destination,
+        )
+    }
+
+    /// A version 2 `LOCAL` header, for connections such as health checks that
+    /// are not relayed on behalf of a client.
+    pub fn local() -> Self {
+        Self {
+            version: Version::V2,
+            command: Command::Local,
+            transport: Transport::Unknown,
+            source: None,
+            destination: None,
+            tlvs: ProxyTlvs::default(),
+        }
+    }
+
+    /// Set the protocol version to encode.
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set the TLVs to send. Only version 2 carries them.
+    pub fn with_tlvs(mut self, tlvs: ProxyTlvs) -> Self {
+        self.tlvs = tlvs;
+        self
+    }
+
+    /// Encode the header.
+    ///
+    /// When the source and destination families differ, IPv4 addresses are
+    /// sent as IPv4-mapped IPv6. Fails when a version 1 header has TLVs or
+    /// relays datagrams, or a version 2 header exceeds 64 KiB.
+    ///
+    /// ```rust
+    /// use real::proxy_protocol::{self, ProxyHeader, Version};
+    ///
+    /// let header = ProxyHeader::new("203.0.113.9:51234".parse().unwrap(), "192.0.2.1:443".parse().unwrap())
+    ///     .with_version(Version::V1);
+    /// let bytes = header.to_bytes().unwrap();
+    /// assert_eq!(bytes, b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 443\r\n");
+    /// assert_eq!(proxy_protocol::parse(&bytes).unwrap(), Some((header, bytes.len())));
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self.version {
+            Version::V1 => self.encode_v1(),
+            Version::V2 => self.encode_v2(),
+        }
+    }
+
+    /// Write the header to the start of an outgoing connection.
+    #[cfg(feature = "tokio")]
+    pub async fn write_to<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin + ?Sized,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let bytes = self
+            .to_bytes()
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        writer.write_all(&bytes).await
+    }
+
+    /// The addresses to send, with matching families.
+    fn addrs(&self) -> Option<(SocketAddr, SocketAddr)> {
+        if self.command == Command::Local {
+            return None;
+        }
+        let (source, destination) = self.source.zip(self.destination)?;
+        if source.is_ipv4() == destination.is_ipv4() {
+            return Some((source, destination));
+        }
+        let v6 = |addr: SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+            IpAddr::V6(_) => addr,
+        };
+        Some((v6(source), v6(destination)))
+    }
+
+    fn encode_v1(&self) -> Result<Vec<u8>> {
+        if self.tlvs != ProxyTlvs::default() {
+            return Err(invalid("v1 headers cannot carry TLVs"));
+        }
+        // v1 only names TCP families
+        if self.transport == Transport::Datagram && self.addrs().is_some() {
+            return Err(invalid("v1 headers cannot relay datagrams"));
+        }
+        let line = match self.addrs() {
+            Some((source, destination)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            ),
+            None => "PROXY UNKNOWN\r\n".to_string(),
+        };
+        Ok(line.into_bytes())
+    }
+
+    fn encode_v2(&self) -> Result<Vec<u8>> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        let command = match self.command {
+            Command::Local => 0x20,
+            Command::Proxy => 0x21,
+        };
+        let transport = match self.transport {
+            Transport::Unknown => 0x00,
+            Transport::Stream => 0x01,
+            Transport::Datagram => 0x02,
+        };
+        let addrs = self.addrs();
+        let family = match addrs {
+            None => 0x00,
+            Some((source, _)) if source.is_ipv4() => 0x10,
+            Some(_) => 0x20,
+        };
+        buf.extend_from_slice(&[command, family | transport, 0, 0]);
+
+        if let Some((source, destination)) = addrs {
+            for addr in [source, destination] {
+                match addr.ip() {
+                    IpAddr::V4(ip) => buf.extend_from_slice(&ip.octets()),
+                    IpAddr::V6(ip) => buf.extend_from_slice(&ip.octets()),
+                }
+            }
+            buf.extend_from_slice(&source.port().to_be_bytes());
+            buf.extend_from_slice(&destination.port().to_be_bytes());
+        }
+        tlv::encode(&self.tlvs, &mut buf)?;
+
+        let len =
+            u16::try_from(buf.len() - V2_HEADER_LEN).map_err(|_| invalid("v2 header too long"))?;
+        buf[14..V2_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_protocol::{SslInfo, parse};
+
+    #[test]
+    fn test_round_trip() {
+        let tlvs = ProxyTlvs {
+            authority: Some("api.example.com".to_string()),
+            ssl: Some(SslInfo {
+                client_ssl: true,
+                version: Some("TLSv1.3".to_string()),
+                ..SslInfo::default()
+            }),
+            aws_vpce_id: Some("vpce-0123456789abcdef0".to_string()),
+            azure_link_id: Some(7),
+            other: vec![(0xE0, vec![1, 2])],
+            ..ProxyTlvs::default()
+        };
+        let headers = [
+            ProxyHeader::new(
+                "[2001:db8::1]:4711".parse().unwrap(),
+                "[2001:db8::2]:443".parse().unwrap(),
+            )
+            .with_tlvs(tlvs),
+            ProxyHeader::for_client(
+                "203.0.113.9".parse().unwrap(),
+                "192.0.2.1:443".parse().unwrap(),
+            ),
+            ProxyHeader::local(),
+        ];
+        for header in headers {
+            let bytes = header.to_bytes().unwrap();
+            assert_eq!(parse(&bytes).unwrap(), Some((header, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn test_mixed_families() {
+        let header = ProxyHeader::new(
+            "203.0.113.9:51234".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+        );
+        let (parsed, _) = parse(&header.to_bytes().unwrap()).unwrap().unwrap();
+        assert_eq!(
+            parsed.source,
+            Some("[::ffff:203.0.113.9]:51234".parse().unwrap())
+        );
+
+        let v1 = header.with_version(Version::V1).to_bytes().unwrap();
+        assert!(v1.starts_with(b"PROXY TCP6 ::ffff:203.0.113.9 2001:db8::2 "));
+
+        // v1 has no UDP families
+        let mut datagram = ProxyHeader::new(
+            "203.0.113.9:51234".parse().unwrap(),
+            "192.0.2.1:443".parse().unwrap(),
+        );
+        datagram.transport = Transport::Datagram;
+        assert!(datagram.clone().to_bytes().is_ok());
+        assert!(datagram.with_version(Version::V1).to_bytes().is_err());
+
+        let tlvs = ProxyTlvs {
+            unique_id: Some(vec![1]),
+            ..ProxyTlvs::default()
+        };
+        assert!(
+            ProxyHeader::local()
+                .with_version(Version::V1)
+                .with_tlvs(tlvs)
+                .to_bytes()
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_write_to() {
+        use tokio::io::AsyncReadExt;
+
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let header = ProxyHeader::new(
+            "203.0.113.9:51234".parse().unwrap(),
+            "192.0.2.1:443".parse().unwrap(),
+        );
+        header.write_to(&mut client).await.unwrap();
+        drop(client);
+
+        let mut bytes = Vec::new();
+        server.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(parse(&bytes).unwrap(), Some((header, 28)));
+    }
+}
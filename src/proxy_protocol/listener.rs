@@ -0,0 +1,335 @@
+/* src/proxy_protocol/listener.rs */
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+use super::{ProxyHeader, parse};
+
+/// How long a new connection may take to send its PROXY header by default.
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many connections may be sending their PROXY header at once by default.
+const DEFAULT_MAX_PENDING: usize = 1024;
+
+/// The addresses of a connection accepted by [`ProxyProtocolListener`].
+///
+/// With the `axum` feature, this is available as `ConnectInfo<ProxiedAddr>` and
+/// [`RealIpLayer`](crate::RealIpLayer) uses [`client`](Self::client) as the fallback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxiedAddr {
+    /// The load balancer that opened the TCP connection.
+    pub peer: SocketAddr,
+    /// The PROXY header it sent.
+    pub header: ProxyHeader,
+}
+
+impl ProxiedAddr {
+    /// The original client address, if the header carried one.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.header.source
+    }
+
+    /// The address the client connected to, if the header carried one.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.header.destination
+    }
+
+    /// The original client address, or the peer for `LOCAL` and `UNKNOWN` headers.
+    pub fn client(&self) -> SocketAddr {
+        self.header.source.unwrap_or(self.peer)
+    }
+}
+
+/// A TCP stream with its PROXY header stripped.
+#[derive(Debug)]
+pub struct ProxiedStream {
+    inner: TcpStream,
+    addr: ProxiedAddr,
+    // Bytes read past the header, returned before reading from `inner` again
+    buffered: Vec<u8>,
+    pos: usize,
+}
+
+impl ProxiedStream {
+    /// Read and strip the PROXY header of a freshly accepted stream.
+    pub async fn accept(mut stream: TcpStream, peer: SocketAddr) -> io::Result<Self> {
+        let mut buf = Vec::with_capacity(512);
+        loop {
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            match parse(&buf) {
+                Ok(Some((header, len))) => {
+                    return Ok(Self {
+                        inner: stream,
+                        addr: ProxiedAddr { peer, header },
+                        buffered: buf.split_off(len),
+                        pos: 0,
+                    });
+                }
+                Ok(None) => buf.reserve(512),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+    }
+
+    /// The addresses of this connection.
+    pub fn addr(&self) -> &ProxiedAddr {
+        &self.addr
+    }
+
+    /// The underlying TCP stream.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.inner
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.buffered.len() {
+            let n = buf.remaining().min(this.buffered.len() - this.pos);
+            buf.put_slice(&this.buffered[this.pos..this.pos + n]);
+            this.pos += n;
+            if this.pos == this.buffered.len() {
+                this.buffered = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// A TCP listener for connections from a load balancer that sends PROXY headers.
+///
+/// Only put this behind a load balancer: any client that can reach it directly
+/// chooses its own address.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "axum")]
+/// # async fn run() -> std::io::Result<()> {
+/// use axum::{Router, routing::get};
+/// use real::RealIpLayer;
+/// use real::proxy_protocol::{ProxiedAddr, ProxyProtocolListener};
+///
+/// let app = Router::new()
+///     .route("/", get(|| async { "ok" }))
+///     .layer(RealIpLayer::default());
+/// let listener = ProxyProtocolListener::bind("0.0.0.0:3000").await?;
+/// axum::serve(listener, app.into_make_service_with_connect_info::<ProxiedAddr>()).await
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ProxyProtocolListener {
+    inner: TcpListener,
+    header_timeout: Duration,
+    max_pending: usize,
+    pending: JoinSet<Option<ProxiedStream>>,
+}
+
+impl ProxyProtocolListener {
+    /// Bind a new listener to `addr`.
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self::new(TcpListener::bind(addr).await?))
+    }
+
+    /// Wrap an existing listener.
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            inner: listener,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            max_pending: DEFAULT_MAX_PENDING,
+            pending: JoinSet::new(),
+        }
+    }
+
+    /// Set how long a connection may take to send its PROXY header (default 5 seconds).
+    ///
+    /// Together with [`max_pending`](Self::max_pending), this bounds what idle
+    /// connections can hold: at most `max_pending` of them, each for at most
+    /// this long.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Set how many connections may be sending their PROXY header at once
+    /// (default 1024, at least 1).
+    ///
+    /// At the limit, no new connections are accepted until a pending one sends
+    /// its header or times out; the rest wait in the listen backlog.
+    pub fn max_pending(mut self, max: usize) -> Self {
+        self.max_pending = max.max(1);
+        self
+    }
+
+    /// The local address this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Accept the next connection that sent a valid PROXY header.
+    ///
+    /// Headers are read concurrently, so a slow connection does not hold up the
+    /// others, up to [`max_pending`](Self::max_pending) at a time. Connections
+    /// with an invalid header, or none within the timeout, are closed.
+    pub async fn accept(&mut self) -> io::Result<(ProxiedStream, SocketAddr)> {
+        loop {
+            tokio::select! {
+                accepted = self.inner.accept(), if self.pending.len() < self.max_pending => {
+                    let (stream, peer) = accepted?;
+                    let timeout = self.header_timeout;
+                    self.pending.spawn(async move {
+                        tokio::time::timeout(timeout, ProxiedStream::accept(stream, peer))
+                            .await
+                            .ok()?
+                            .ok()
+                    });
+                }
+                Some(joined) = self.pending.join_next(), if !self.pending.is_empty() => {
+                    if let Ok(Some(stream)) = joined {
+                        let peer = stream.addr.peer;
+                        return Ok((stream, peer));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::serve::Listener for ProxyProtocolListener {
+    type Io = ProxiedStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match Self::accept(self).await {
+                Ok(accepted) => return accepted,
+                Err(err) => handle_accept_error(err).await,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Self::local_addr(self)
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::extract::connect_info::Connected<axum::serve::IncomingStream<'_, ProxyProtocolListener>>
+    for ProxiedAddr
+{
+    fn connect_info(stream: axum::serve::IncomingStream<'_, ProxyProtocolListener>) -> Self {
+        stream.io().addr().clone()
+    }
+}
+
+#[cfg(feature = "axum")]
+async fn handle_accept_error(err: io::Error) {
+    if matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    ) {
+        return;
+    }
+    // Usually too many open files; back off instead of spinning
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_listener_strips_header() {
+        let mut listener = ProxyProtocolListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // A client that never sends a header must not block the next one
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 443\r\nhello")
+            .await
+            .unwrap();
+
+        let (mut stream, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert_eq!(stream.addr().client(), "203.0.113.9:51234".parse().unwrap());
+
+        let mut body = [0; 5];
+        stream.read_exact(&mut body).await.unwrap();
+        assert_eq!(&body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_max_pending() {
+        let mut listener = ProxyProtocolListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .header_timeout(Duration::from_millis(100))
+            .max_pending(1);
+        let addr = listener.local_addr().unwrap();
+
+        // The silent client takes the only slot until it times out
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 443\r\n")
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(stream.addr().client(), "203.0.113.9:51234".parse().unwrap());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(listener.pending.is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+/* src/proxy_protocol/tlv.rs */
+
+use super::invalid;
+use crate::error::Result;
+
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_CRC32C: u8 = 0x03;
+const PP2_TYPE_NOOP: u8 = 0x04;
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+const PP2_SUBTYPE_SSL_SIG_ALG: u8 = 0x24;
+const PP2_SUBTYPE_SSL_KEY_ALG: u8 = 0x25;
+const PP2_TYPE_NETNS: u8 = 0x30;
+const PP2_TYPE_AWS: u8 = 0xEA;
+const PP2_SUBTYPE_AWS_VPCE_ID: u8 = 0x01;
+const PP2_TYPE_AZURE: u8 = 0xEE;
+const PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID: u8 = 0x01;
+
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+const PP2_CLIENT_CERT_SESS: u8 = 0x04;
+
+/// The TLVs of a version 2 PROXY header.
+///
+/// Private link services can authorize on the endpoint as well as the address:
+///
+/// ```rust
+/// use real::proxy_protocol::ProxyTlvs;
+///
+/// fn allowed(tlvs: &ProxyTlvs) -> bool {
+///     tlvs.aws_vpce_id.as_deref() == Some("vpce-0123456789abcdef0")
+/// }
+/// # assert!(!allowed(&ProxyTlvs::default()));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyTlvs {
+    /// `PP2_TYPE_ALPN`: the negotiated application protocol, such as `h2`.
+    pub alpn: Option<Vec<u8>>,
+    /// `PP2_TYPE_AUTHORITY`: the host name the client asked for, usually the TLS SNI.
+    pub authority: Option<String>,
+    /// `PP2_TYPE_UNIQUE_ID`: an opaque connection identifier of up to 128 bytes.
+    pub unique_id: Option<Vec<u8>>,
+    /// `PP2_TYPE_SSL`: how the client connected over TLS.
+    pub ssl: Option<SslInfo>,
+    /// `PP2_TYPE_NETNS`: the network namespace the connection was accepted in.
+    pub netns: Option<String>,
+    /// AWS `PP2_TYPE_AWS` (`0xEA`): the VPC endpoint ID of a PrivateLink connection.
+    pub aws_vpce_id: Option<String>,
+    /// Azure `PP2_TYPE_AZURE` (`0xEE`): the Private Link endpoint link ID.
+    pub azure_link_id: Option<u32>,
+    /// Any other TLVs, as type and raw value, in order.
+    pub other: Vec<(u8, Vec<u8>)>,
+}
+
+/// The `PP2_TYPE_SSL` TLV.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SslInfo {
+    /// The client connected over TLS.
+    pub client_ssl: bool,
+    /// The client sent a certificate over this connection.
+    pub cert_in_connection: bool,
+    /// The client sent a certificate at least once over this TLS session.
+    pub cert_in_session: bool,
+    /// The certificate verification result; zero on success.
+    pub verify: u32,
+    /// `PP2_SUBTYPE_SSL_VERSION`, such as `TLSv1.3`.
+    pub version: Option<String>,
+    /// `PP2_SUBTYPE_SSL_CN`: the common name of the client certificate subject.
+    pub common_name: Option<String>,
+    /// `PP2_SUBTYPE_SSL_CIPHER`, such as `ECDHE-RSA-AES128-GCM-SHA256`.
+    pub cipher: Option<String>,
+    /// `PP2_SUBTYPE_SSL_SIG_ALG`: the signature algorithm of the client certificate.
+    pub sig_alg: Option<String>,
+    /// `PP2_SUBTYPE_SSL_KEY_ALG`: the key algorithm of the client certificate.
+    pub key_alg: Option<String>,
+}
+
+impl SslInfo {
+    /// Whether the client presented a certificate and it was verified.
+    pub fn cert_verified(&self) -> bool {
+        (self.cert_in_connection || self.cert_in_session) && self.verify == 0
+    }
+}
+
+/// Parse the TLVs that follow the address block of `header`, the complete
+/// version 2 header, starting at `start`.
+///
+/// A `PP2_TYPE_CRC32C` checksum is verified when present.
+pub(super) fn parse(header: &[u8], start: usize) -> Result<ProxyTlvs> {
+    let mut tlvs = ProxyTlvs::default();
+
+    for (offset, kind, value) in split(&header[start..])? {
+        match kind {
+            PP2_TYPE_ALPN => tlvs.alpn = Some(value.to_vec()),
+            PP2_TYPE_AUTHORITY => tlvs.authority = Some(text(value)?),
+            PP2_TYPE_CRC32C => {
+                let expected = <[u8; 4]>::try_from(value)
+                    .map_err(|_| invalid("CRC32C TLV must be 4 bytes"))?;
+                // The checksum covers the whole header with its own value zeroed
+                let at = start + offset + 3;
+                let actual = crc32c(
+                    header
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &b)| if (at..at + 4).contains(&i) { 0 } else { b }),
+                );
+                if actual != u32::from_be_bytes(expected) {
+                    return Err(invalid("CRC32C mismatch"));
+                }
+            }
+            PP2_TYPE_NOOP => {}
+            PP2_TYPE_UNIQUE_ID => tlvs.unique_id = Some(value.to_vec()),
+            PP2_TYPE_SSL => tlvs.ssl = Some(parse_ssl(value)?),
+            PP2_TYPE_NETNS => tlvs.netns = Some(text(value)?),
+            PP2_TYPE_AWS if value.first() == Some(&PP2_SUBTYPE_AWS_VPCE_ID) => {
+                tlvs.aws_vpce_id = Some(text(&value[1..])?);
+            }
+            PP2_TYPE_AZURE if value.first() == Some(&PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID) => {
+                let id = value
+                    .get(1..5)
+                    .ok_or_else(|| invalid("short Azure link ID TLV"))?;
+                // Unlike the rest of the protocol, Azure sends this little-endian
+                tlvs.azure_link_id = Some(u32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+            }
+            _ => tlvs.other.push((kind, value.to_vec())),
+        }
+    }
+
+    Ok(tlvs)
+}
+
+fn parse_ssl(value: &[u8]) -> Result<SslInfo> {
+    if value.len() < 5 {
+        return Err(invalid("short SSL TLV"));
+    }
+    let client = value[0];
+    let mut ssl = SslInfo {
+        client_ssl: client & PP2_CLIENT_SSL != 0,
+        cert_in_connection: client & PP2_CLIENT_CERT_CONN != 0,
+        cert_in_session: client & PP2_CLIENT_CERT_SESS != 0,
+        verify: u32::from_be_bytes([value[1], value[2], value[3], value[4]]),
+        ..SslInfo::default()
+    };
+
+    for (_, kind, value) in split(&value[5..])? {
+        let field = match kind {
+            PP2_SUBTYPE_SSL_VERSION => &mut ssl.version,
+            PP2_SUBTYPE_SSL_CN => &mut ssl.common_name,
+            PP2_SUBTYPE_SSL_CIPHER => &mut ssl.cipher,
+            PP2_SUBTYPE_SSL_SIG_ALG => &mut ssl.sig_alg,
+            PP2_SUBTYPE_SSL_KEY_ALG => &mut ssl.key_alg,
+            _ => continue,
+        };
+        *field = Some(text(value)?);
+    }
+
+    Ok(ssl)
+}
+
+/// Append the TLVs of `tlvs` to `out`, known types first.
+pub(super) fn encode(tlvs: &ProxyTlvs, out: &mut Vec<u8>) -> Result<()> {
+    if let Some(alpn) = &tlvs.alpn {
+        put(out, PP2_TYPE_ALPN, alpn)?;
+    }
+    if let Some(authority) = &tlvs.authority {
+        put(out, PP2_TYPE_AUTHORITY, authority.as_bytes())?;
+    }
+    if let Some(unique_id) = &tlvs.unique_id {
+        put(out, PP2_TYPE_UNIQUE_ID, unique_id)?;
+    }
+    if let Some(ssl) = &tlvs.ssl {
+        let client = [
+            (ssl.client_ssl, PP2_CLIENT_SSL),
+            (ssl.cert_in_connection, PP2_CLIENT_CERT_CONN),
+            (ssl.cert_in_session, PP2_CLIENT_CERT_SESS),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |client, (_, bit)| client | bit);
+        let mut value = vec![client];
+        value.extend_from_slice(&ssl.verify.to_be_bytes());
+        for (kind, field) in [
+            (PP2_SUBTYPE_SSL_VERSION, &ssl.version),
+            (PP2_SUBTYPE_SSL_CN, &ssl.common_name),
+            (PP2_SUBTYPE_SSL_CIPHER, &ssl.cipher),
+            (PP2_SUBTYPE_SSL_SIG_ALG, &ssl.sig_alg),
+            (PP2_SUBTYPE_SSL_KEY_ALG, &ssl.key_alg),
+        ] {
+            if let Some(field) = field {
+                put(&mut value, kind, field.as_bytes())?;
+            }
+        }
+        put(out, PP2_TYPE_SSL, &value)?;
+    }
+    if let Some(netns) = &tlvs.netns {
+        put(out, PP2_TYPE_NETNS, netns.as_bytes())?;
+    }
+    if let Some(vpce_id) = &tlvs.aws_vpce_id {
+        let mut value = vec![PP2_SUBTYPE_AWS_VPCE_ID];
+        value.extend_from_slice(vpce_id.as_bytes());
+        put(out, PP2_TYPE_AWS, &value)?;
+    }
+    if let Some(link_id) = tlvs.azure_link_id {
+        let mut value = vec![PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID];
+        value.extend_from_slice(&link_id.to_le_bytes());
+        put(out, PP2_TYPE_AZURE, &value)?;
+    }
+    for (kind, value) in &tlvs.other {
+        put(out, *kind, value)?;
+    }
+    Ok(())
+}
+
+fn put(out: &mut Vec<u8>, kind: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len()).map_err(|_| invalid("TLV value too long"))?;
+    out.push(kind);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+/// Split a TLV sequence into offset, type and value.
+fn split(mut buf: &[u8]) -> Result<Vec<(usize, u8, &[u8])>> {
+    let mut tlvs = Vec::new();
+    let mut offset = 0;
+    while !buf.is_empty() {
+        let [kind, hi, lo, ..] = *buf else {
+            return Err(invalid("truncated TLV"));
+        };
+        let len = 3 + usize::from(u16::from_be_bytes([hi, lo]));
+        let value = buf.get(3..len).ok_or_else(|| invalid("truncated TLV"))?;
+        tlvs.push((offset, kind, value));
+        offset += len;
+        buf = &buf[len..];
+    }
+    Ok(tlvs)
+}
+
+fn text(value: &[u8]) -> Result<String> {
+    String::from_utf8(value.to_vec()).map_err(|_| invalid("TLV value is not UTF-8"))
+}
+
+/// CRC-32C (Castagnoli), as used by `PP2_TYPE_CRC32C`.
+pub(super) fn crc32c(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82F6_3B78 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tlv(kind: u8, value: &[u8]) -> Vec<u8> {
+        let mut tlv = vec![kind];
+        tlv.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        tlv.extend_from_slice(value);
+        tlv
+    }
+
+    #[test]
+    fn test_parse_tlvs() {
+        let mut ssl = vec![PP2_CLIENT_SSL | PP2_CLIENT_CERT_CONN, 0, 0, 0, 0];
+        ssl.extend(tlv(PP2_SUBTYPE_SSL_VERSION, b"TLSv1.3"));
+        ssl.extend(tlv(PP2_SUBTYPE_SSL_CN, b"client.example"));
+
+        let mut buf = tlv(PP2_TYPE_AUTHORITY, b"api.example.com");
+        buf.extend(tlv(PP2_TYPE_SSL, &ssl));
+        buf.extend(tlv(PP2_TYPE_AWS, b"\x01vpce-0123456789abcdef0"));
+        buf.extend(tlv(PP2_TYPE_AZURE, &[0x01, 0x78, 0x56, 0x34, 0x12]));
+        buf.extend(tlv(0xE0, b"\x01"));
+
+        let tlvs = parse(&buf, 0).unwrap();
+        assert_eq!(tlvs.authority.as_deref(), Some("api.example.com"));
+        assert_eq!(tlvs.aws_vpce_id.as_deref(), Some("vpce-0123456789abcdef0"));
+        assert_eq!(tlvs.azure_link_id, Some(0x1234_5678));
+        assert_eq!(tlvs.other, vec![(0xE0, vec![0x01])]);
+
+        let ssl = tlvs.ssl.unwrap();
+        assert!(ssl.client_ssl && ssl.cert_verified());
+        assert_eq!(ssl.version.as_deref(), Some("TLSv1.3"));
+        assert_eq!(ssl.common_name.as_deref(), Some("client.example"));
+    }
+
+    #[test]
+    fn test_crc32c() {
+        // Check value from RFC 3720
+        assert_eq!(crc32c(*b"123456789"), 0xE306_9283);
+
+        let mut buf = tlv(PP2_TYPE_CRC32C, &[0; 4]);
+        let crc = crc32c(buf.iter().copied());
+        buf[3..7].copy_from_slice(&crc.to_be_bytes());
+        assert!(parse(&buf, 0).is_ok());
+        buf[4] ^= 1;
+        assert!(parse(&buf, 0).is_err());
+        assert!(parse(&[PP2_TYPE_AUTHORITY, 0, 9, b'x'], 0).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+/* src/rule.rs */
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::forwarded::{self, NodeName};
+use crate::headers::HeaderSource;
+
+/// One entry of a parsed forwarding chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEntry {
+    /// An address, with its port if the header carried one.
+    Addr(ClientAddr),
+    /// A well-formed entry that hides the address, such as `unknown` or `_hidden`
+    /// in a `Forwarded` header.
+    Opaque(String),
+    /// A token that could not be parsed.
+    Invalid(String),
+}
+
+impl ChainEntry {
+    /// The address of this entry, if it has one.
+    pub fn addr(&self) -> Option<ClientAddr> {
+        match self {
+            Self::Addr(addr) => Some(*addr),
+            _ => None,
+        }
+    }
+}
+
+/// A parser for proprietary headers, plugged in with [`HeaderFormat::Custom`].
+///
+/// Closures of type `Fn(&str) -> Vec<ChainEntry>` implement this trait.
+pub trait HeaderParser: Send + Sync {
+    /// Parse a header value into its chain, from the client to the closest proxy.
+    fn parse(&self, value: &str) -> Vec<ChainEntry>;
+}
+
+impl<F> HeaderParser for F
+where
+    F: Fn(&str) -> Vec<ChainEntry> + Send + Sync,
+{
+    fn parse(&self, value: &str) -> Vec<ChainEntry> {
+        self(value)
+    }
+}
+
+/// A selection for proxies with rules of their own, plugged in with
+/// [`ChainSelection::Custom`].
+///
+/// Closures of type `Fn(&[ChainEntry], Option<IpAddr>) -> Option<(usize, ClientAddr)>`
+/// implement this trait.
+pub trait ChainSelector: Send + Sync {
+    /// Select the client from a chain sent by the socket `peer`, returning its
+    /// index and address. `None` lets the next rule apply.
+    fn select(&self, chain: &[ChainEntry], peer: Option<IpAddr>) -> Option<(usize, ClientAddr)>;
+}
+
+impl<F> ChainSelector for F
+where
+    F: Fn(&[ChainEntry], Option<IpAddr>) -> Option<(usize, ClientAddr)> + Send + Sync,
+{
+    fn select(&self, chain: &[ChainEntry], peer: Option<IpAddr>) -> Option<(usize, ClientAddr)> {
+        self(chain, peer)
+    }
+}
+
+impl fmt::Debug for dyn ChainSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("..")
+    }
+}
+
+/// How a header value is parsed.
+#[derive(Clone)]
+pub enum HeaderFormat {
+    /// A single address, such as `X-Real-IP` or `CF-Connecting-IP`.
+    SingleIp,
+    /// A comma-separated list of addresses, such as `X-Forwarded-For`.
+    CommaList,
+    /// An RFC 7239 `Forwarded` header; the `for` nodes form the chain.
+    Forwarded,
+    /// An address always followed by a port, such as `CloudFront-Viewer-Address`.
+    /// IPv6 addresses may appear without brackets.
+    IpPort,
+    /// A user-supplied parser.
+    Custom(Arc<dyn HeaderParser>),
+}
+
+impl fmt::Debug for HeaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SingleIp => f.write_str("SingleIp"),
+            Self::CommaList => f.write_str("CommaList"),
+            Self::Forwarded => f.write_str("Forwarded"),
+            Self::IpPort => f.write_str("IpPort"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Which entry of a parsed chain is the client.
+#[derive(Debug, Clone, Default)]
+pub enum ChainSelection {
+    /// Follow the extractor-wide `use_first_forwarded` and `trusted_proxies` settings.
+    #[default]
+    Inherit,
+    /// The leftmost address. Anyone can set it, so only use it behind proxies
+    /// that overwrite the header.
+    Leftmost,
+    /// The rightmost address.
+    Rightmost,
+    /// Walk from the right, skipping addresses inside these ranges. The socket
+    /// peer must be inside them as well.
+    RightmostUntrusted(CidrSet),
+    /// The n-th address from the right, like Envoy's `xff_num_trusted_hops`.
+    /// A shorter chain fails the extraction, and private addresses are not rejected.
+    TrustedHops(usize),
+    /// Like `TrustedHops`, with the count read from another header, such as
+    /// `Akamai-Origin-Hop`. Without a valid count the rule does not apply.
+    HopsFromHeader(String),
+    /// A user-supplied selection, such as the walks of the [`compat`](crate::compat) modes.
+    Custom(Arc<dyn ChainSelector>),
+}
+
+impl ChainSelection {
+    /// A selection made by `selector`.
+    pub fn custom(selector: impl ChainSelector + 'static) -> Self {
+        Self::Custom(Arc::new(selector))
+    }
+}
+
+/// A condition on the request that must hold for a [`HeaderRule`] to apply.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    /// The rightmost address of a comma-separated chain header must be inside
+    /// these ranges. Behind a trusted proxy that appends to `X-Forwarded-For`,
+    /// this checks the hop that connected to it, such as a CDN in front of a
+    /// load balancer.
+    LastHopIn {
+        /// The chain header, usually `x-forwarded-for`.
+        header: String,
+        /// Ranges the last hop must be inside.
+        ranges: CidrSet,
+    },
+    /// A header must be sent exactly once with this value, ignoring ASCII case
+    /// and surrounding whitespace, like Azure Front Door's `X-Azure-FDID`.
+    HeaderEquals {
+        /// Header name.
+        header: String,
+        /// Expected value.
+        value: String,
+    },
+}
+
+impl Condition {
+    /// Whether the condition holds for `headers`.
+    pub fn holds<H: HeaderSource + ?Sized>(&self, headers: &H) -> bool {
+        match self {
+            Self::LastHopIn { header, ranges } => headers
+                .get_all(header)
+                .last()
+                .and_then(|line| line.rsplit(',').next())
+                .and_then(|token| token.parse::<ClientAddr>().ok())
+                .is_some_and(|addr| ranges.contains(&addr.ip)),
+            Self::HeaderEquals { header, value } => matches!(
+                headers.get_all(header).as_slice(),
+                [sent] if sent.trim().eq_ignore_ascii_case(value.trim())
+            ),
+        }
+    }
+}
+
+/// A header to read the client address from, with its format and trust requirements.
+///
+/// ```rust
+/// use real::{ChainSelection, CidrSet, HeaderRule, IpExtractor};
+///
+/// let extractor = IpExtractor::new().with_rules(vec![
+///     HeaderRule::single_ip("cf-connecting-ip")
+///         .trusted_peers("173.245.48.0/20".parse::<CidrSet>().unwrap()),
+///     HeaderRule::comma_list("x-forwarded-for").selection(ChainSelection::TrustedHops(1)),
+/// ]);
+/// # let _ = extractor;
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeaderRule {
+    /// Header name.
+    pub name: String,
+    /// How the header value is parsed.
+    pub format: HeaderFormat,
+    /// Which chain entry is the client.
+    pub selection: ChainSelection,
+    /// Peers allowed to set the header. `None` allows any peer.
+    pub trusted_peers: Option<CidrSet>,
+    /// Further conditions that must all hold for the header to be honored.
+    pub conditions: Vec<Condition>,
+    /// Read only the first line of a repeated header instead of combining them.
+    pub first_only: bool,
+    /// Fail when the header is honored but holds no usable address, instead of
+    /// trying the next rule or the fallback.
+    pub authoritative: bool,
+    /// `name` interned by [`IpExtractor::build`](crate::IpExtractor::build).
+    #[cfg(feature = "http")]
+    pub(crate) header_name: Option<http::HeaderName>,
+}
+
+impl HeaderRule {
+    /// Create a rule for `name` with the given format.
+    pub fn new(name: impl Into<String>, format: HeaderFormat) -> Self {
+        Self {
+            name: name.into(),
+            format,
+            selection: ChainSelection::Inherit,
+            trusted_peers: None,
+            conditions: Vec::new(),
+            first_only: false,
+            authoritative: false,
+            #[cfg(feature = "http")]
+            header_name: None,
+        }
+    }
+
+    /// A rule for a header holding a single address.
+    pub fn single_ip(name: impl Into<String>) -> Self {
+        Self::new(name, HeaderFormat::SingleIp)
+    }
+
+    /// A rule for a header holding a comma-separated list of addresses.
+    pub fn comma_list(name: impl Into<String>) -> Self {
+        Self::new(name, HeaderFormat::CommaList)
+    }
+
+    /// A rule for an RFC 7239 `Forwarded` header.
+    pub fn forwarded(name: impl Into<String>) -> Self {
+        Self::new(name, HeaderFormat::Forwarded)
+    }
+
+    /// A rule for a header holding an `ip:port` value.
+    pub fn ip_port(name: impl Into<String>) -> Self {
+        Self::new(name, HeaderFormat::IpPort)
+    }
+
+    /// A rule for a header parsed by `parser`.
+    pub fn custom(name: impl Into<String>, parser: impl HeaderParser + 'static) -> Self {
+        Self::new(name, HeaderFormat::Custom(Arc::new(parser)))
+    }
+
+    /// A rule with the format of a well-known header, used by [`IpExtractor::with_headers`].
+    ///
+    /// Unknown headers are treated as comma-separated lists.
+    ///
+    /// [`IpExtractor::with_headers`]: crate::IpExtractor::with_headers
+    pub fn infer(name: impl Into<String>) -> Self {
+        let name = name.into();
+        let format = match name.to_ascii_lowercase().as_str() {
+            "x-real-ip" | "cf-connecting-ip" | "cf-connecting-ipv6" | "true-client-ip"
+            | "fastly-client-ip" | "fly-client-ip" | "x-azure-clientip" | "x-azure-socketip" => {
+                HeaderFormat::SingleIp
+            }
+            "forwarded" => HeaderFormat::Forwarded,
+            "cloudfront-viewer-address" => HeaderFormat::IpPort,
+            _ => HeaderFormat::CommaList,
+        };
+        Self::new(name, format)
+    }
+
+    /// Set which chain entry is the client.
+    pub fn selection(mut self, selection: ChainSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Only honor the header when the socket peer is inside `peers`.
+    pub fn trusted_peers(mut self, peers: impl Into<CidrSet>) -> Self {
+        self.trusted_peers = Some(peers.into());
+        self
+    }
+
+    /// Only honor the header when `condition` holds as well.
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Read only the first line of a repeated header, like nginx does for
+    /// headers other than `X-Forwarded-For`.
+    pub fn first_only(mut self) -> Self {
+        self.first_only = true;
+        self
+    }
+
+    /// Fail with [`NoValidIp`](crate::RealIpError::NoValidIp) when the header
+    /// is honored but holds no usable address, for proxies that always set it.
+    /// Otherwise the next rule or the fallback is used, which is the proxy's own
+    /// address.
+    pub fn authoritative(mut self) -> Self {
+        self.authoritative = true;
+        self
+    }
+
+    /// Whether `peer` may set this header.
+    pub fn allows_peer(&self, peer: Option<IpAddr>) -> bool {
+        match &self.trusted_peers {
+            Some(trusted) => peer.is_some_and(|peer| trusted.contains(&peer)),
+            None => true,
+        }
+    }
+
+    /// Whether the header is honored for a request from `peer` with `headers`.
+    pub fn applies<H: HeaderSource + ?Sized>(&self, peer: Option<IpAddr>, headers: &H) -> bool {
+        self.allows_peer(peer)
+            && self
+                .conditions
+                .iter()
+                .all(|condition| condition.holds(headers))
+    }
+
+    /// Parse a header value into its chain, from the client to the closest proxy.
+    pub fn parse(&self, value: &str) -> Vec<ChainEntry> {
+        match &self.format {
+            HeaderFormat::SingleIp => vec![parse_entry(value)],
+            HeaderFormat::CommaList => value.split(',').map(parse_entry).collect(),
+            HeaderFormat::Forwarded => parse_forwarded(value),
+            HeaderFormat::IpPort => vec![parse_ip_port(value)],
+            HeaderFormat::Custom(parser) => parser.parse(value),
+        }
+    }
+}
+
+/// The header rules to honor for requests from peers inside a set of ranges.
+///
+/// An [`IpExtractor`](crate::IpExtractor) with peer rules uses the first one
+/// matching the socket peer, and the socket address when none match. A rule
+/// without header rules also uses the socket address.
+#[derive(Debug, Clone)]
+pub struct PeerRule {
+    /// Peers the rule applies to.
+    pub peers: CidrSet,
+    /// Header rules to check, in order of preference.
+    pub rules: Vec<HeaderRule>,
+}
+
+impl PeerRule {
+    /// Honor `rules` for peers inside `peers`.
+    pub fn new(peers: impl Into<CidrSet>, rules: Vec<HeaderRule>) -> Self {
+        Self {
+            peers: peers.into(),
+            rules,
+        }
+    }
+
+    /// Whether the rule applies to `peer`.
+    pub fn matches(&self, peer: Option<IpAddr>) -> bool {
+        peer.is_some_and(|peer| self.peers.contains(&peer))
+    }
+}
+
+fn parse_entry(token: &str) -> ChainEntry {
+    match token.parse::<ClientAddr>() {
+        Ok(addr) => ChainEntry::Addr(addr),
+        Err(_) => ChainEntry::Invalid(token.trim().to_string()),
+    }
+}
+
+fn parse_forwarded(value: &str) -> Vec<ChainEntry> {
+    match forwarded::parse_for_chain(value) {
+        Ok(nodes) => nodes
+            .iter()
+            .map(|node| match (&node.name, node.addr()) {
+                (_, Some(addr)) => ChainEntry::Addr(addr),
+                (NodeName::Obfuscated(name), None) => ChainEntry::Opaque(name.clone()),
+                _ => ChainEntry::Opaque("unknown".to_string()),
+            })
+            .collect(),
+        Err(_) => vec![ChainEntry::Invalid(value.trim().to_string())],
+    }
+}
+
+fn parse_ip_port(value: &str) -> ChainEntry {
+    let value = value.trim();
+    if !value.starts_with('[')
+        && let Some((ip, port)) = value.rsplit_once(':')
+        && let (Ok(ip), Ok(port)) = (ip.parse::<IpAddr>(), port.parse::<u16>())
+    {
+        return ChainEntry::Addr(ClientAddr {
+            ip,
+            port: Some(port),
+        });
+    }
+    parse_entry(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let addr = |s: &str| ChainEntry::Addr(s.parse().unwrap());
+
+        assert_eq!(
+            HeaderRule::comma_list("x-forwarded-for").parse("192.0.2.1, bogus"),
+            vec![addr("192.0.2.1"), ChainEntry::Invalid("bogus".to_string())]
+        );
+        assert_eq!(
+            HeaderRule::single_ip("x-real-ip").parse("192.0.2.1, 192.0.2.2"),
+            vec![ChainEntry::Invalid("192.0.2.1, 192.0.2.2".to_string())]
+        );
+        assert_eq!(
+            HeaderRule::forwarded("forwarded").parse("for=_hidden, for=192.0.2.1"),
+            vec![ChainEntry::Opaque("_hidden".to_string()), addr("192.0.2.1")]
+        );
+        assert_eq!(
+            HeaderRule::ip_port("cloudfront-viewer-address").parse("2001:db8::17:46532"),
+            vec![addr("[2001:db8::17]:46532")]
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let rule = HeaderRule::ip_port("cloudfront-viewer-address").when(Condition::LastHopIn {
+            header: "x-forwarded-for".to_string(),
+            ranges: "130.176.0.0/16".parse().unwrap(),
+        });
+        let headers = vec![(
+            "X-Forwarded-For".to_string(),
+            "203.0.113.9, 130.176.1.1".to_string(),
+        )];
+        assert!(rule.applies(None, &headers));
+        assert!(!rule.applies(
+            None,
+            &vec![(
+                "x-forwarded-for".to_string(),
+                "130.176.1.1, 203.0.113.9".to_string()
+            )]
+        ));
+        assert!(!rule.applies(None, &Vec::<(String, String)>::new()));
+    }
+
+    #[test]
+    fn test_custom_parser() {
+        let rule = HeaderRule::custom("x-edge-client", |value: &str| {
+            vec![match value.strip_prefix("ip=") {
+                Some(ip) => ChainEntry::Addr(ip.parse().unwrap()),
+                None => ChainEntry::Invalid(value.to_string()),
+            }]
+        });
+        assert_eq!(
+            rule.parse("ip=198.51.100.4"),
+            vec![ChainEntry::Addr("198.51.100.4".parse().unwrap())]
+        );
+    }
+}
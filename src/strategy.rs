@@ -0,0 +1,387 @@
+/* src/strategy.rs */
+
+//! Composable strategies for choosing the client address.
+//!
+//! Each strategy answers one question, such as "what did the `N` proxies in
+//! front of us see?", and returns `None` when it cannot tell. [`Chain`] tries
+//! several in order, so the fallback is explicit:
+//!
+//! ```rust
+//! use real::{CidrSet, Chain, HeaderSource, RemoteAddr, RightmostTrustedRange, Strategy};
+//! use std::collections::HashMap;
+//!
+//! let proxies: CidrSet = "10.0.0.0/8".parse().unwrap();
+//! let strategy = Chain::new()
+//!     .or(RightmostTrustedRange::new(proxies))
+//!     .or(RemoteAddr);
+//!
+//! let mut headers = HashMap::new();
+//! headers.insert("x-forwarded-for".to_string(), "198.51.100.7, 10.0.0.3".to_string());
+//!
+//! let addr = strategy.client_addr(&headers, Some("10.0.0.2".parse().unwrap()));
+//! assert_eq!(addr, Some("198.51.100.7".parse().unwrap()));
+//!
+//! // Not from a proxy: the socket address
+//! let addr = strategy.client_addr(&headers, Some("203.0.113.9".parse().unwrap()));
+//! assert_eq!(addr, Some("203.0.113.9".parse().unwrap()));
+//! ```
+
+use std::sync::Arc;
+
+use crate::addr::ClientAddr;
+use crate::cidr::CidrSet;
+use crate::extractor::IpExtractor;
+use crate::headers::HeaderSource;
+use crate::rule::{ChainEntry, HeaderRule};
+
+/// Header that proxies append the address of their peer to.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A way of choosing the client address of a request.
+pub trait Strategy: Send + Sync {
+    /// The client of a request with `headers` from the socket address `peer`,
+    /// or `None` if this strategy cannot tell.
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr>;
+}
+
+impl<S: Strategy + ?Sized> Strategy for Arc<S> {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        (**self).client_addr(headers, peer)
+    }
+}
+
+impl<S: Strategy + ?Sized> Strategy for Box<S> {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        (**self).client_addr(headers, peer)
+    }
+}
+
+impl Strategy for IpExtractor {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        self.extract_addr(headers, peer.map(|peer| peer.to_string()))
+    }
+}
+
+/// The socket address. Always correct for direct connections, and the last
+/// link of most chains.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RemoteAddr;
+
+impl Strategy for RemoteAddr {
+    fn client_addr(&self, _: &dyn HeaderSource, peer: Option<ClientAddr>) -> Option<ClientAddr> {
+        peer
+    }
+}
+
+/// A header holding one address, such as `X-Real-IP` or `CF-Connecting-IP`,
+/// set by the proxy in front of the service. A header sent more than once is
+/// ignored.
+#[derive(Debug, Clone)]
+pub struct SingleIpHeader {
+    header: String,
+}
+
+impl SingleIpHeader {
+    /// Read the address from `header`.
+    pub fn new(header: impl Into<String>) -> Self {
+        Self {
+            header: header.into(),
+        }
+    }
+}
+
+impl Strategy for SingleIpHeader {
+    fn client_addr(&self, headers: &dyn HeaderSource, _: Option<ClientAddr>) -> Option<ClientAddr> {
+        match headers.get_all(&self.header).as_slice() {
+            [value] => value.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// The rightmost address of `X-Forwarded-For` that is not private, for
+/// services whose proxies all have private addresses.
+#[derive(Debug, Clone)]
+pub struct RightmostNonPrivate {
+    header: String,
+}
+
+impl RightmostNonPrivate {
+    /// Read `X-Forwarded-For`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read another chain header, such as `Forwarded`.
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+impl Default for RightmostNonPrivate {
+    fn default() -> Self {
+        Self {
+            header: FORWARDED_FOR.to_string(),
+        }
+    }
+}
+
+impl Strategy for RightmostNonPrivate {
+    fn client_addr(&self, headers: &dyn HeaderSource, _: Option<ClientAddr>) -> Option<ClientAddr> {
+        for entry in chain(headers, &self.header).iter().rev() {
+            // Anything unparsable was not written by our proxies
+            let addr = entry.addr()?;
+            if IpExtractor::private_reason(&addr.ip).is_none() {
+                return Some(addr);
+            }
+        }
+        None
+    }
+}
+
+/// The leftmost address of `X-Forwarded-For` that is not private.
+///
+/// **This is spoofable**: any client can send its own `X-Forwarded-For` and
+/// choose this address. Only use it for analytics, never for access control
+/// or rate limiting.
+#[derive(Debug, Clone)]
+pub struct LeftmostNonPrivate {
+    header: String,
+}
+
+impl LeftmostNonPrivate {
+    /// Read `X-Forwarded-For`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read another chain header, such as `Forwarded`.
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+impl Default for LeftmostNonPrivate {
+    fn default() -> Self {
+        Self {
+            header: FORWARDED_FOR.to_string(),
+        }
+    }
+}
+
+impl Strategy for LeftmostNonPrivate {
+    fn client_addr(&self, headers: &dyn HeaderSource, _: Option<ClientAddr>) -> Option<ClientAddr> {
+        chain(headers, &self.header)
+            .iter()
+            .filter_map(ChainEntry::addr)
+            .find(|addr| IpExtractor::private_reason(&addr.ip).is_none())
+    }
+}
+
+/// The `count`-th address of `X-Forwarded-For` from the right, for services
+/// behind exactly `count` proxies that each append their peer.
+#[derive(Debug, Clone)]
+pub struct RightmostTrustedCount {
+    header: String,
+    count: usize,
+}
+
+impl RightmostTrustedCount {
+    /// Trust `count` proxies. With `0`, nothing is returned.
+    pub fn new(count: usize) -> Self {
+        Self {
+            header: FORWARDED_FOR.to_string(),
+            count,
+        }
+    }
+
+    /// Read another chain header, such as `Forwarded`.
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+impl Strategy for RightmostTrustedCount {
+    fn client_addr(&self, headers: &dyn HeaderSource, _: Option<ClientAddr>) -> Option<ClientAddr> {
+        let chain = chain(headers, &self.header);
+        let index = chain
+            .len()
+            .checked_sub(self.count)
+            .filter(|_| self.count > 0)?;
+        chain[index].addr()
+    }
+}
+
+/// The rightmost address of `X-Forwarded-For` outside the proxy ranges, for
+/// requests whose socket peer is inside them.
+#[derive(Debug, Clone)]
+pub struct RightmostTrustedRange {
+    header: String,
+    trusted: CidrSet,
+}
+
+impl RightmostTrustedRange {
+    /// Trust proxies inside `trusted`.
+    pub fn new(trusted: impl Into<CidrSet>) -> Self {
+        Self {
+            header: FORWARDED_FOR.to_string(),
+            trusted: trusted.into(),
+        }
+    }
+
+    /// Read another chain header, such as `Forwarded`.
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+impl Strategy for RightmostTrustedRange {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        if !self.trusted.contains(&peer?.ip) {
+            return None;
+        }
+        for entry in chain(headers, &self.header).iter().rev() {
+            let addr = entry.addr()?;
+            if !self.trusted.contains(&addr.ip) {
+                return Some(addr);
+            }
+        }
+        None
+    }
+}
+
+/// Strategies tried in order; the first answer wins.
+#[derive(Clone, Default)]
+pub struct Chain {
+    strategies: Vec<Arc<dyn Strategy>>,
+}
+
+impl Chain {
+    /// An empty chain, which never answers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Try `strategy` after the ones already in the chain.
+    pub fn or(mut self, strategy: impl Strategy + 'static) -> Self {
+        self.strategies.push(Arc::new(strategy));
+        self
+    }
+}
+
+impl std::fmt::Debug for Chain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chain({} strategies)", self.strategies.len())
+    }
+}
+
+impl Strategy for Chain {
+    fn client_addr(
+        &self,
+        headers: &dyn HeaderSource,
+        peer: Option<ClientAddr>,
+    ) -> Option<ClientAddr> {
+        self.strategies
+            .iter()
+            .find_map(|strategy| strategy.client_addr(headers, peer))
+    }
+}
+
+/// The parsed chain of `header`, with repeated lines combined.
+fn chain(headers: &dyn HeaderSource, header: &str) -> Vec<ChainEntry> {
+    let values = headers.get_all(header);
+    if values.is_empty() {
+        return Vec::new();
+    }
+    HeaderRule::infer(header).parse(&values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn headers(forwarded_for: &str) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("x-forwarded-for".to_string(), forwarded_for.to_string());
+        headers
+    }
+
+    fn addr(addr: &str) -> Option<ClientAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_chain_strategies() {
+        let headers = headers("192.0.2.1, 198.51.100.7, 10.0.0.3");
+        let peer = addr("10.0.0.2");
+
+        assert_eq!(
+            RightmostNonPrivate::new().client_addr(&headers, peer),
+            addr("198.51.100.7")
+        );
+        assert_eq!(
+            LeftmostNonPrivate::new().client_addr(&headers, peer),
+            addr("192.0.2.1")
+        );
+        assert_eq!(
+            RightmostTrustedCount::new(2).client_addr(&headers, peer),
+            addr("198.51.100.7")
+        );
+        assert_eq!(
+            RightmostTrustedCount::new(4).client_addr(&headers, peer),
+            None
+        );
+
+        let ranges = RightmostTrustedRange::new("10.0.0.0/8".parse::<CidrSet>().unwrap());
+        assert_eq!(ranges.client_addr(&headers, peer), addr("198.51.100.7"));
+        assert_eq!(ranges.client_addr(&headers, addr("203.0.113.9")), None);
+
+        let spoofed = self::headers("bogus, 10.0.0.3");
+        assert_eq!(RightmostNonPrivate::new().client_addr(&spoofed, peer), None);
+        assert_eq!(ranges.client_addr(&spoofed, peer), None);
+    }
+
+    #[test]
+    fn test_single_header_and_chain() {
+        let mut headers = vec![("X-Real-IP".to_string(), "198.51.100.7".to_string())];
+        let strategy = Chain::new()
+            .or(SingleIpHeader::new("x-real-ip"))
+            .or(RemoteAddr);
+
+        assert_eq!(
+            strategy.client_addr(&headers, addr("10.0.0.2")),
+            addr("198.51.100.7")
+        );
+        headers.push(("x-real-ip".to_string(), "192.0.2.1".to_string()));
+        assert_eq!(
+            strategy.client_addr(&headers, addr("10.0.0.2")),
+            addr("10.0.0.2")
+        );
+        assert_eq!(Chain::new().client_addr(&headers, addr("10.0.0.2")), None);
+    }
+}